@@ -1,5 +1,8 @@
 // CSS Data Struct
 
+use std::error::Error;
+use std::fmt;
+
 #[derive(Debug)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>
@@ -29,19 +32,19 @@ pub struct Declaration {
     pub value: Value,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
     ColorValue(Color)
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize)]
 pub enum Unit {
     Px,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -51,6 +54,22 @@ pub struct Color {
 
 pub type Specificity = (usize, usize, usize);
 
+/// A selector list that could not be parsed, located at the byte offset
+/// where parsing stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Error for SelectorError {}
+
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         let Selector::Simple(ref simple) = *self;
@@ -71,62 +90,162 @@ impl Value {
     }
 }
 
+/// Values are written back as CSS, e.g. `50px` or `#4cb4e7`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Keyword(ref keyword) => write!(f, "{}", keyword),
+            Value::Length(length, Unit::Px) => write!(f, "{}px", length),
+            Value::ColorValue(color) => {
+                write!(f, "#{:02x}{:02x}{:02x}", color.r, color.g, color.b)?;
+                if color.a != 255 {
+                    write!(f, "{:02x}", color.a)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
 // Parsing
-pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+
+/// A part of a stylesheet that was skipped because it could not be parsed,
+/// located where the problem was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    pub message: String,
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
 }
 
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+/// Parse a stylesheet. As the CSS spec asks, an invalid declaration is
+/// dropped up to its semicolon and a rule with an invalid selector is
+/// dropped with its block; each is reported with a warning and parsing
+/// carries on after it.
+pub fn parse(source: String) -> (Stylesheet, Vec<ParseWarning>) {
+    let mut parser = Parser::new(source);
+    let rules = parser.parse_rules();
+
+    (Stylesheet { rules }, parser.warnings)
+}
+
+/// Parse a declaration list without braces, as found in a `style` attribute.
+/// Invalid declarations are dropped as in `parse`.
+pub fn parse_declarations(source: String) -> (Vec<Declaration>, Vec<ParseWarning>) {
+    let mut parser = Parser::new(source);
+    let declarations = parser.parse_declaration_list(false);
+
+    (declarations, parser.warnings)
+}
+
+/// Parse a comma-separated selector list on its own, such as `div, p.note`.
+pub fn parse_selector_list(source: String) -> Result<Vec<Selector>, SelectorError> {
+    let mut parser = Parser::new(source);
+    let mut selectors = Vec::new();
+    loop {
+        parser.consume_whitespace();
+        let selector = parser.parse_simple_selector()
+            .map_err(|warning| SelectorError { message: warning.message, offset: warning.offset })?;
+        selectors.push(Selector::Simple(selector));
+        parser.consume_whitespace();
+        if parser.eof() { break }
+        match parser.next_char() {
+            ',' => { parser.consume_char(); },
+            c => return Err(parser.selector_error(format!("unexpected {:?} in selector list", c))),
+        }
+    }
+
+    return Ok(selectors);
+}
+
+type ParseResult<T> = Result<T, ParseWarning>;
+
 struct Parser {
     pos: usize,
     input: String,
+    warnings: Vec<ParseWarning>,
 }
 
 impl Parser {
+    fn new(input: String) -> Parser {
+        Parser { pos: 0, input, warnings: Vec::new() }
+    }
+
     fn parse_rules(&mut self) -> Vec<Rule> {
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
             if self.eof() { break }
-            rules.push(self.parse_rule());
+            if self.next_char() == '@' {
+                let start = self.pos;
+                self.consume_char();
+                let name = self.parse_identify();
+                let warning = self.warning_at(start, format!("unsupported at-rule @{}", name));
+                self.warnings.push(warning);
+                self.skip_rule(true);
+                continue;
+            }
+            if let Some(rule) = self.parse_rule() {
+                rules.push(rule);
+            }
         }
 
         return rules;
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    fn parse_rule(&mut self) -> Option<Rule> {
+        match self.parse_selectors() {
+            Ok(selectors) => Some(Rule {
+                selectors,
+                declarations: self.parse_declarations(),
+            }),
+            Err(warning) => {
+                self.warnings.push(warning);
+                self.skip_rule(false);
+                None
+            },
         }
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            selectors.push(Selector::Simple(self.parse_simple_selector()?));
             self.consume_whitespace();
+            if self.eof() {
+                return Err(self.warning("unexpected end of input in selector list".to_string()));
+            }
             match self.next_char() {
                 ',' => { self.consume_char(); self.consume_whitespace(); },
                 '{' => break,
-                c => panic!("Unexpected character {:?} in selector list", c),
+                c => return Err(self.warning(format!("unexpected {:?} in selector list", c))),
             }
         }
 
-        return selectors;
+        return Ok(selectors);
     }
 
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    fn parse_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
+        let start = self.pos;
         let mut selector = SimpleSelector { tag_name: None, id: None, class: Vec::new() };
         while !self.eof() {
             match self.next_char() {
                 '#' => {
                     self.consume_char();
-                    selector.id = Some(self.parse_identify());
+                    selector.id = Some(self.parse_name("an id")?);
                 },
                 '.' => {
                     self.consume_char();
-                    selector.class.push(self.parse_identify())
+                    selector.class.push(self.parse_name("a class name")?)
                 },
                 '*' => {
                     self.consume_char();
@@ -137,86 +256,267 @@ impl Parser {
                 _ => break
             }
         }
+        if self.pos == start {
+            return Err(self.warning("expected a selector".to_string()));
+        }
 
-        return selector;
+        return Ok(selector);
     }
 
     fn parse_identify(&mut self) -> String {
         self.consume_while(valid_identifier_char)
     }
 
+    /// An identifier that must not be empty, such as the name after `#`.
+    fn parse_name(&mut self, what: &str) -> ParseResult<String> {
+        let name = self.parse_identify();
+        if name.is_empty() {
+            return Err(self.warning(format!("expected {}", what)));
+        }
+
+        Ok(name)
+    }
+
     fn parse_declarations(&mut self) -> Vec<Declaration> {
         assert_eq!(self.consume_char(), '{');
+        self.parse_declaration_list(true)
+    }
+
+    /// Declarations up to the `}` closing a block, or to the end of input
+    /// outside one.
+    fn parse_declaration_list(&mut self, in_block: bool) -> Vec<Declaration> {
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
-                self.consume_char();
+            if self.eof() {
+                if in_block {
+                    // The block is closed for us, keeping what it held.
+                    let warning = self.warning("unexpected end of input in block".to_string());
+                    self.warnings.push(warning);
+                }
                 break;
             }
-            declarations.push(self.parse_declaration());
+            match self.next_char() {
+                '}' if in_block => {
+                    self.consume_char();
+                    break;
+                },
+                '}' => {
+                    let warning = self.warning("unexpected '}'".to_string());
+                    self.warnings.push(warning);
+                    self.consume_char();
+                },
+                // Empty declarations are allowed.
+                ';' => {
+                    self.consume_char();
+                },
+                _ => match self.parse_declaration() {
+                    Ok(declaration) => declarations.push(declaration),
+                    Err(warning) => {
+                        self.warnings.push(warning);
+                        self.skip_declaration();
+                    },
+                },
+            }
         }
 
         return declarations;
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
-        let name = self.parse_identify();
+    fn parse_declaration(&mut self) -> ParseResult<Declaration> {
+        let name = self.parse_name("a property name")?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
+        if self.eof() || self.next_char() != ':' {
+            return Err(self.warning(format!("expected ':' after {:?}", name)));
+        }
+        self.consume_char();
         self.consume_whitespace();
-        let value = self.parse_value();
+        let value = self.parse_value()?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
+        // The last declaration in a block or list may omit its semicolon.
+        if self.eof() || self.next_char() == '}' {
+            return Ok(Declaration { name, value });
+        }
+        if self.next_char() != ';' {
+            return Err(self.warning(format!("expected ';' after the value of {:?}", name)));
+        }
+        self.consume_char();
 
-        Declaration {
+        Ok(Declaration {
             name,
             value,
-        }
+        })
     }
 
-    fn parse_value(&mut self) -> Value {
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        if self.eof() {
+            return Err(self.warning("expected a value, found end of input".to_string()));
+        }
         match self.next_char() {
-            '0'...'9' => self.parse_length(),
+            '0'..='9' | '.' => self.parse_length(),
             '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identify()),
+            c if valid_identifier_char(c) => Ok(Value::Keyword(self.parse_identify())),
+            c => Err(self.warning(format!("expected a value, found {:?}", c))),
         }
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        let length = self.parse_float()?;
+        Ok(Value::Length(length, self.parse_unit(length)?))
     }
 
-    fn parse_float(&mut self) -> f32 {
+    fn parse_float(&mut self) -> ParseResult<f32> {
+        let start = self.pos;
         let s = self.consume_while(|c| match c {
-            '0'...'9' | '.' => true,
+            '0'..='9' | '.' => true,
             _ => false,
         });
 
-        s.parse().unwrap()
+        s.parse().map_err(|_| self.warning_at(start, format!("invalid number {:?}", s)))
     }
 
-    fn parse_unit(&mut self) -> Unit {
+    fn parse_unit(&mut self, length: f32) -> ParseResult<Unit> {
+        let start = self.pos;
         match &*self.parse_identify().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit")
+            "px" => Ok(Unit::Px),
+            // Zero needs no unit.
+            "" if length == 0.0 => Ok(Unit::Px),
+            "" => Err(self.warning_at(start, "expected a unit".to_string())),
+            unit => Err(self.warning_at(start, format!("unrecognized unit {:?}", unit))),
         }
     }
 
-    fn parse_color(&mut self) -> Value {
+    fn parse_color(&mut self) -> ParseResult<Value> {
+        let start = self.pos;
         assert_eq!(self.consume_char(), '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
+        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+        if hex.len() != 6 {
+            return Err(self.warning_at(start, format!("invalid color #{}", hex)));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+        Ok(Value::ColorValue(Color {
+            r: channel(0),
+            g: channel(2),
+            b: channel(4),
             a: 255
-        })
+        }))
+    }
+
+    fn selector_error(&self, message: String) -> SelectorError {
+        SelectorError { message, offset: self.pos }
+    }
+
+    fn warning(&self, message: String) -> ParseWarning {
+        self.warning_at(self.pos, message)
+    }
+
+    fn warning_at(&self, offset: usize, message: String) -> ParseWarning {
+        let before = &self.input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseWarning {
+            message,
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    // 错误恢复
+    /// Skip the rest of a rule that could not be parsed, up to and including
+    /// its block. An at-rule without a block ends at a semicolon instead.
+    fn skip_rule(&mut self, at_rule: bool) {
+        while !self.eof() {
+            if self.skip_comment() {
+                continue;
+            }
+            match self.consume_char() {
+                ';' if at_rule => return,
+                '{' => {
+                    self.skip_block('}');
+                    return;
+                },
+                '(' => self.skip_block(')'),
+                '[' => self.skip_block(']'),
+                quote @ ('"' | '\'') => self.skip_string(quote),
+                _ => {},
+            }
+        }
+    }
+
+    /// Skip the rest of a declaration, up to and including its semicolon, but
+    /// not the `}` closing the block it is in. Semicolons in nested blocks,
+    /// as in `url(a;b.png)`, do not end it.
+    fn skip_declaration(&mut self) {
+        while !self.eof() {
+            if self.skip_comment() {
+                continue;
+            }
+            if self.next_char() == '}' {
+                return;
+            }
+            match self.consume_char() {
+                ';' => return,
+                '{' => self.skip_block('}'),
+                '(' => self.skip_block(')'),
+                '[' => self.skip_block(']'),
+                quote @ ('"' | '\'') => self.skip_string(quote),
+                _ => {},
+            }
+        }
+    }
+
+    /// Skip past the `close` matching a bracket just consumed, with any
+    /// nested blocks. Closing brackets of another kind are skipped too.
+    fn skip_block(&mut self, close: char) {
+        let mut closing = vec![close];
+        while let Some(&close) = closing.last() {
+            if self.eof() {
+                return;
+            }
+            if self.skip_comment() {
+                continue;
+            }
+            match self.consume_char() {
+                c if c == close => { closing.pop(); },
+                '{' => closing.push('}'),
+                '(' => closing.push(')'),
+                '[' => closing.push(']'),
+                quote @ ('"' | '\'') => self.skip_string(quote),
+                _ => {},
+            }
+        }
     }
 
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos .. self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    /// Skip a `/* … */` comment if one starts here. An unterminated comment
+    /// runs to the end of input.
+    fn skip_comment(&mut self) -> bool {
+        if !self.input[self.pos..].starts_with("/*") {
+            return false;
+        }
+        match self.input[self.pos + 2..].find("*/") {
+            Some(index) => self.pos += 2 + index + 2,
+            None => {
+                let warning = self.warning("unterminated comment".to_string());
+                self.warnings.push(warning);
+                self.pos = self.input.len();
+            },
+        }
+
+        true
+    }
+
+    /// Skip past the end of a string whose opening quote was just consumed.
+    /// An unescaped newline ends the string too.
+    fn skip_string(&mut self, quote: char) {
+        while !self.eof() {
+            match self.consume_char() {
+                '\\' if !self.eof() => { self.consume_char(); },
+                '\n' => return,
+                c if c == quote => return,
+                _ => {},
+            }
+        }
     }
 
     // 通用函数
@@ -224,20 +524,13 @@ impl Parser {
         self.input[self.pos..].chars().next().unwrap()
     }
 
-    fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
-    }
-
     fn eof(&self) -> bool {
         self.pos >= self.input.len()
     }
 
     fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let ( _, crt_char ) = iter.next().unwrap();
-        let ( next_pos, _ ) = iter.next().unwrap_or((1, ' '));
-
-        self.pos += next_pos;
+        let crt_char = self.next_char();
+        self.pos += crt_char.len_utf8();
 
         crt_char
     }
@@ -252,14 +545,81 @@ impl Parser {
         return result;
     }
 
+    /// Skip white space and comments.
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        loop {
+            self.consume_while(char::is_whitespace);
+            if !self.skip_comment() {
+                break;
+            }
+        }
     }
 }
 
+/// Name characters per CSS: ASCII letters, digits, `-`, `_` and anything
+/// beyond ASCII.
 fn valid_identifier_char(c: char) -> bool {
     match c {
-        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true,
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
+        c if !c.is_ascii() => true,
         _ => false,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multibyte_character_at_end_of_input() {
+        let (stylesheet, warnings) = parse("div{}é".to_string());
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(warnings.len(), 1);
+
+        let (declarations, warnings) = parse_declarations("color: red; é".to_string());
+        assert_eq!(declarations.len(), 1);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn non_ascii_keyword() {
+        let (declarations, warnings) = parse_declarations("font-family: café".to_string());
+        assert!(warnings.is_empty());
+        assert_eq!(declarations[0].value, Value::Keyword("café".to_string()));
+    }
+
+    #[test]
+    fn invalid_declarations_are_dropped() {
+        let (stylesheet, warnings) = parse("div { color: red width: 5em; height: 10px }".to_string());
+        let names: Vec<_> = stylesheet.rules[0].declarations.iter().map(|d| &*d.name).collect();
+        assert_eq!(names, ["height"]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line, warnings[0].column), (1, 18));
+    }
+    #[test]
+    fn comments_are_skipped() {
+        let source = "/* a */ div /* b */ { /* c */ color: /* d */ red /* e */; /* f */ }\n/* g";
+        let (stylesheet, warnings) = parse(source.to_string());
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(stylesheet.rules[0].declarations[0].value, Value::Keyword("red".to_string()));
+        let messages: Vec<_> = warnings.iter().map(|w| &*w.message).collect();
+        assert_eq!(messages, ["unterminated comment"]);
+
+        let (declarations, warnings) = parse_declarations("color: red/* ; */; width: 5px".to_string());
+        assert_eq!(declarations.len(), 2);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn invalid_declarations_skip_nested_blocks() {
+        let source = "background: url(a;b.png); grid: [a;b] 1px; x: {;}; color: red";
+        let (declarations, warnings) = parse_declarations(source.to_string());
+        let names: Vec<_> = declarations.iter().map(|d| &*d.name).collect();
+        assert_eq!(names, ["color"]);
+        assert_eq!(warnings.len(), 3);
+
+        let (stylesheet, _) = parse("div { a: (}); color: red } p { color: blue }".to_string());
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(stylesheet.rules[0].declarations.len(), 1);
+    }
 }
\ No newline at end of file
@@ -1,16 +1,34 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::ops::Index;
+use std::slice;
+use std::vec;
+
+use serde::ser::{ Serialize, SerializeMap, Serializer };
+
+/// Identifies a node within its `Document`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub struct NodeId(usize);
 
 #[derive(Debug)]
 pub struct Node {
-    pub children: Vec<Node>,
     pub node_type: NodeType,
+    /// Where the node came from, for nodes produced by `html::parse`.
+    pub source: Option<SourceSpan>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
 }
 
 #[derive(Debug)]
 pub enum NodeType {
     Text(String),
     Element(ElementData),
+    Comment(String),
 }
 
 #[derive(Debug)]
@@ -19,21 +37,765 @@ pub struct ElementData {
     pub attributes: AttrMap,
 }
 
-pub type AttrMap = HashMap<String, String>;
+/// An element's attributes in source order, looked up by name.
+#[derive(Clone, Default)]
+pub struct AttrMap {
+    entries: Vec<(String, String)>,
+    /// Where each name is in `entries`.
+    index: HashMap<String, usize>,
+}
 
-impl Node {
-    pub fn text(data: String) -> Node {
-        Node { children: Vec::new(), node_type: NodeType::Text(data), }
+/// The attributes of an `AttrMap` in order, as `(name, value)` pairs.
+pub struct AttrIter<'a>(slice::Iter<'a, (String, String)>);
+
+/// A document. Its nodes live in an arena, linked to their parent and
+/// siblings by `NodeId`; the root is an `html` element, with the DOCTYPE
+/// in front of it.
+#[derive(Debug)]
+pub struct Document {
+    pub doctype: Option<Doctype>,
+    nodes: Vec<Node>,
+    root: NodeId,
+}
+
+/// Why a DOM mutation was refused. The document is left unchanged.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DomError {
+    /// The node would become its own ancestor.
+    Cycle,
+    /// Only elements have children.
+    LeafParent,
+    /// The reference node is not a child of the given parent.
+    NotAChild,
+    /// Attributes belong to elements.
+    NotAnElement,
+    /// A class name must be non-empty and free of white space.
+    InvalidClass,
+    /// The root stays at the top of the document.
+    RootMoved,
+}
+
+impl fmt::Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match *self {
+            DomError::Cycle => "node would contain itself",
+            DomError::LeafParent => "text and comment nodes have no children",
+            DomError::NotAChild => "node is not a child of the parent",
+            DomError::NotAnElement => "node is not an element",
+            DomError::InvalidClass => "class names cannot be empty or contain white space",
+            DomError::RootMoved => "the root node cannot be moved",
+        };
+        write!(f, "{}", message)
     }
+}
+
+impl Error for DomError {}
+
+/// A tree of nodes outside any document, for building documents by hand
+/// with `Tree::elem` and `Tree::text`, then adding them with
+/// `Document::from_tree` or `Document::create_tree`.
+#[derive(Debug)]
+pub struct Tree {
+    node_type: NodeType,
+    children: Vec<Tree>,
+}
+
+/// A position in the source text.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize)]
+pub struct SourcePosition {
+    /// Byte offset.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+}
+
+/// The source text of a node, from the start of its first byte to just past
+/// its last. Elements whose tags were implied cover only their content.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize)]
+pub struct SourceSpan {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
+}
 
-    pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+impl Node {
+    fn new(node_type: NodeType) -> Node {
         Node {
-            children,
+            node_type,
+            source: None,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            prev_sibling: None,
+            next_sibling: None,
+        }
+    }
+
+    /// A one-line `Debug` view of the node without its children, for dumps
+    /// of trees built on top of the DOM.
+    pub fn summary(&self) -> Summary<'_> {
+        Summary(self)
+    }
+
+    /// The node on one line for the indented dumps: an element's start tag,
+    /// quoted text, or the comment.
+    pub fn label(&self) -> String {
+        match self.node_type {
+            NodeType::Element(ref elem) => {
+                let mut label = format!("<{}", elem.tag_name);
+                for (name, value) in &elem.attributes {
+                    label.push_str(&format!(" {}={:?}", name, value));
+                }
+                label.push('>');
+                label
+            },
+            NodeType::Text(ref text) => format!("{:?}", text),
+            NodeType::Comment(ref data) => format!("<!--{}-->", data),
+        }
+    }
+
+    /// ` at {span}` for a node parsed from source, or nothing, to follow the
+    /// node in the style and layout dumps.
+    pub fn location(&self) -> String {
+        match self.source {
+            Some(ref span) => format!(" at {}", span),
+            None => String::new(),
+        }
+    }
+}
+
+impl Tree {
+    pub fn text(data: String) -> Tree {
+        Tree { node_type: NodeType::Text(data), children: Vec::new() }
+    }
+
+    pub fn comment(data: String) -> Tree {
+        Tree { node_type: NodeType::Comment(data), children: Vec::new() }
+    }
+
+    pub fn elem(name: String, attrs: AttrMap, children: Vec<Tree>) -> Tree {
+        Tree {
             node_type: NodeType::Element(ElementData {
                 tag_name: name,
                 attributes: attrs,
-            })
+            }),
+            children,
+        }
+    }
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document::new()
+    }
+}
+
+impl Document {
+    /// An empty document: an `html` element with no children.
+    pub fn new() -> Document {
+        Document::from_tree(Tree::elem("html".to_string(), AttrMap::new(), Vec::new()))
+    }
+
+    /// A document whose root is `tree`.
+    pub fn from_tree(tree: Tree) -> Document {
+        let mut document = Document { doctype: None, nodes: Vec::new(), root: NodeId(0) };
+        document.root = document.create_tree(tree);
+
+        document
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].last_child
+    }
+
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].prev_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].next_sibling
+    }
+
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children { document: self, next: self.first_child(id) }
+    }
+
+    // 遍历
+    /// The node and everything below it, each parent before its children.
+    pub fn preorder(&self, id: NodeId) -> Preorder<'_> {
+        Preorder { document: self, scope: id, next: Some(id) }
+    }
+
+    /// The node and everything below it, each parent after its children.
+    pub fn postorder(&self, id: NodeId) -> Postorder<'_> {
+        Postorder { document: self, scope: id, next: Some(self.deepest_first(id)) }
+    }
+
+    /// Everything below the node, in document order.
+    pub fn descendants(&self, id: NodeId) -> Preorder<'_> {
+        Preorder { document: self, scope: id, next: self.first_child(id) }
+    }
+
+    /// The node's parent, its parent's parent, and so on up to the root.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors { document: self, next: self.parent(id) }
+    }
+
+    /// The text of a text or comment node, or the text of all text nodes
+    /// below an element, concatenated in document order.
+    pub fn text_content(&self, id: NodeId) -> String {
+        match self.nodes[id.0].node_type {
+            NodeType::Text(ref data) | NodeType::Comment(ref data) => data.clone(),
+            NodeType::Element(_) => self.descendants(id)
+                .filter_map(|node| match self.nodes[node.0].node_type {
+                    NodeType::Text(ref data) => Some(&**data),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The first element in the document, in document order, with this `id` attribute.
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.preorder(self.root).find(|&node| match self.nodes[node.0].node_type {
+            NodeType::Element(ref elem) => elem.id().is_some_and(|elem_id| elem_id == id),
+            _ => false,
+        })
+    }
+
+    /// The document indented two spaces per level, one node per line, for
+    /// reading and for golden-file tests.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        if let Some(ref doctype) = self.doctype {
+            out.push_str(&format!("<!DOCTYPE {}>\n", doctype.name));
+        }
+        for node in self.preorder(self.root) {
+            let depth = self.ancestors(node).count();
+            out.push_str(&format!("{}{}\n", "  ".repeat(depth), self.nodes[node.0].label()));
+        }
+
+        out
+    }
+
+    /// The document as pretty-printed JSON; see the `Serialize` impls.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a document always serializes")
+    }
+
+    fn deepest_first(&self, id: NodeId) -> NodeId {
+        let mut node = id;
+        while let Some(child) = self.first_child(node) {
+            node = child;
+        }
+
+        node
+    }
+
+    // 创建节点; 新节点不在树中, 需要再插入
+    pub fn create_text(&mut self, data: String) -> NodeId {
+        self.push(Node::new(NodeType::Text(data)))
+    }
+
+    pub fn create_comment(&mut self, data: String) -> NodeId {
+        self.push(Node::new(NodeType::Comment(data)))
+    }
+
+    pub fn create_element(&mut self, name: String, attrs: AttrMap) -> NodeId {
+        self.push(Node::new(NodeType::Element(ElementData { tag_name: name, attributes: attrs })))
+    }
+
+    /// Add the nodes of `tree`, returning its detached top node.
+    pub fn create_tree(&mut self, tree: Tree) -> NodeId {
+        let id = self.push(Node::new(tree.node_type));
+        for child in tree.children {
+            let child = self.create_tree(child);
+            self.append(id, child);
+        }
+
+        id
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    // 修改树
+    /// Move `child` to the end of `parent`'s children, taking it out of
+    /// wherever it was.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        self.insert_before(parent, child, None)
+    }
+
+    /// Move `child` in front of `reference` among `parent`'s children, or
+    /// to the end when there is no reference.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> Result<(), DomError> {
+        self.check_insert(parent, child)?;
+        if let Some(reference) = reference {
+            self.check_child(parent, reference)?;
+            if reference == child {
+                return Ok(());
+            }
+        }
+
+        self.detach(child);
+        self.link(parent, child, reference);
+        Ok(())
+    }
+
+    /// Take `child` out of `parent`. It stays in the document, detached, and
+    /// can be inserted again.
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        self.check_child(parent, child)?;
+        self.detach(child);
+
+        Ok(())
+    }
+
+    /// Put `new_child` where `old_child` is, detaching `old_child`.
+    pub fn replace_child(&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) -> Result<(), DomError> {
+        self.check_child(parent, old_child)?;
+        self.check_insert(parent, new_child)?;
+        if new_child == old_child {
+            return Ok(());
+        }
+
+        self.detach(new_child);
+        self.link(parent, new_child, Some(old_child));
+        self.detach(old_child);
+        Ok(())
+    }
+
+    /// Set an attribute, lowercasing its name as the parser does.
+    pub fn set_attribute(&mut self, element: NodeId, name: &str, value: String) -> Result<(), DomError> {
+        self.element_mut(element)?.attributes.insert(name.to_ascii_lowercase(), value);
+        Ok(())
+    }
+
+    pub fn remove_attribute(&mut self, element: NodeId, name: &str) -> Result<(), DomError> {
+        self.element_mut(element)?.attributes.remove(&name.to_ascii_lowercase());
+        Ok(())
+    }
+
+    /// Replace the text of a text or comment node, or the whole content of
+    /// an element with a single text node.
+    pub fn set_text(&mut self, node: NodeId, text: String) {
+        match self.nodes[node.0].node_type {
+            NodeType::Text(ref mut data) | NodeType::Comment(ref mut data) => *data = text,
+            NodeType::Element(_) => {
+                while let Some(child) = self.first_child(node) {
+                    self.detach(child);
+                }
+                if !text.is_empty() {
+                    let child = self.create_text(text);
+                    self.link(node, child, None);
+                }
+            },
+        }
+    }
+
+    /// The data of an element, to change its attributes or class list.
+    pub fn element_mut(&mut self, id: NodeId) -> Result<&mut ElementData, DomError> {
+        match self.nodes[id.0].node_type {
+            NodeType::Element(ref mut elem) => Ok(elem),
+            _ => Err(DomError::NotAnElement),
+        }
+    }
+
+    fn check_child(&self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        if self.parent(child) == Some(parent) { Ok(()) } else { Err(DomError::NotAChild) }
+    }
+
+    fn check_insert(&self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        match self.nodes[parent.0].node_type {
+            NodeType::Element(_) => {},
+            _ => return Err(DomError::LeafParent),
+        }
+        if child == self.root {
+            return Err(DomError::RootMoved);
+        }
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            if id == child {
+                return Err(DomError::Cycle);
+            }
+            ancestor = self.parent(id);
+        }
+
+        Ok(())
+    }
+
+    /// Link a detached node in as the last child of `parent`.
+    pub(crate) fn append(&mut self, parent: NodeId, child: NodeId) {
+        self.link(parent, child, None);
+    }
+
+    /// Link a detached node into `parent` in front of `before`, or last.
+    fn link(&mut self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+        let prev = match before {
+            Some(before) => self.nodes[before.0].prev_sibling,
+            None => self.nodes[parent.0].last_child,
+        };
+        {
+            let node = &mut self.nodes[child.0];
+            node.parent = Some(parent);
+            node.prev_sibling = prev;
+            node.next_sibling = before;
+        }
+        match prev {
+            Some(prev) => self.nodes[prev.0].next_sibling = Some(child),
+            None => self.nodes[parent.0].first_child = Some(child),
+        }
+        match before {
+            Some(before) => self.nodes[before.0].prev_sibling = Some(child),
+            None => self.nodes[parent.0].last_child = Some(child),
+        }
+    }
+
+    /// Unlink a node from its parent and siblings, keeping its own children.
+    fn detach(&mut self, id: NodeId) {
+        let parent = match self.nodes[id.0].parent.take() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let prev = self.nodes[id.0].prev_sibling.take();
+        let next = self.nodes[id.0].next_sibling.take();
+        match prev {
+            Some(prev) => self.nodes[prev.0].next_sibling = next,
+            None => self.nodes[parent.0].first_child = next,
+        }
+        match next {
+            Some(next) => self.nodes[next.0].prev_sibling = prev,
+            None => self.nodes[parent.0].last_child = prev,
+        }
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        self.node(id)
+    }
+}
+
+/// The children of a node, first to last.
+pub struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.next_sibling(id);
+
+        Some(id)
+    }
+}
+
+/// A preorder walk of the subtree under `scope`.
+pub struct Preorder<'a> {
+    document: &'a Document,
+    scope: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Preorder<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.first_child(id).or_else(|| {
+            // Climb until there is a next sibling, without leaving the subtree.
+            let mut node = id;
+            loop {
+                if node == self.scope {
+                    return None;
+                }
+                if let Some(sibling) = self.document.next_sibling(node) {
+                    return Some(sibling);
+                }
+                node = self.document.parent(node)?;
+            }
+        });
+
+        Some(id)
+    }
+}
+
+/// A postorder walk of the subtree under `scope`.
+pub struct Postorder<'a> {
+    document: &'a Document,
+    scope: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Postorder<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = if id == self.scope {
+            None
+        } else {
+            match self.document.next_sibling(id) {
+                Some(sibling) => Some(self.document.deepest_first(sibling)),
+                None => self.document.parent(id),
+            }
+        };
+
+        Some(id)
+    }
+}
+
+pub struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.parent(id);
+
+        Some(id)
+    }
+}
+
+// JSON 导出
+// Nodes are written as objects with a lowercase "type". The document nests
+// them as a tree, giving each its id; on their own, as in style and layout
+// trees, they leave out the id and children.
+impl Serialize for Document {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("doctype", &self.doctype)?;
+        map.serialize_entry("root", &TreeRef { document: self, id: self.root })?;
+        map.end()
+    }
+}
+
+struct TreeRef<'a> {
+    document: &'a Document,
+    id: NodeId,
+}
+
+impl<'a> Serialize for TreeRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("id", &self.id)?;
+        self.document[self.id].serialize_fields(&mut map)?;
+        let children: Vec<TreeRef> = self.document.children(self.id)
+            .map(|id| TreeRef { document: self.document, id })
+            .collect();
+        map.serialize_entry("children", &children)?;
+        map.end()
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        self.serialize_fields(&mut map)?;
+        map.end()
+    }
+}
+
+impl Node {
+    fn serialize_fields<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        match self.node_type {
+            NodeType::Element(ref elem) => {
+                map.serialize_entry("type", "element")?;
+                map.serialize_entry("tag_name", &elem.tag_name)?;
+                map.serialize_entry("attributes", &elem.attributes)?;
+            },
+            NodeType::Text(ref text) => {
+                map.serialize_entry("type", "text")?;
+                map.serialize_entry("text", text)?;
+            },
+            NodeType::Comment(ref data) => {
+                map.serialize_entry("type", "comment")?;
+                map.serialize_entry("text", data)?;
+            },
+        }
+        map.serialize_entry("source", &self.source)
+    }
+}
+
+pub struct Summary<'a>(&'a Node);
+
+impl<'a> fmt::Debug for Summary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.node_type {
+            NodeType::Element(ref elem) => write!(f, "<{}>", elem.tag_name)?,
+            NodeType::Text(ref text) => write!(f, "{:?}", text)?,
+            NodeType::Comment(_) => write!(f, "<!-- -->")?,
+        }
+        match self.0.source {
+            Some(ref span) => write!(f, " at {}", span),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SourcePosition {
+    /// The start of the source.
+    pub fn start() -> SourcePosition {
+        SourcePosition { offset: 0, line: 1, column: 1 }
+    }
+
+    /// The position just past `text`, which must start at this position.
+    pub fn advanced_by(self, text: &str) -> SourcePosition {
+        let mut position = self;
+        for c in text.chars() {
+            if c == '\n' {
+                position.line += 1;
+                position.column = 1;
+            } else {
+                position.column += 1;
+            }
+        }
+        position.offset += text.len();
+
+        position
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}-{}:{}", self.start.line, self.start.column, self.end.line, self.end.column)
+    }
+}
+
+impl AttrMap {
+    pub fn new() -> AttrMap {
+        AttrMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.index.get(name).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// Set an attribute, returning the value it replaced. A new attribute
+    /// goes last; an existing one keeps its place.
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        match self.index.get(&name) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.index.insert(name.clone(), self.entries.len());
+                self.entries.push((name, value));
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let removed = self.index.remove(name)?;
+        for i in self.index.values_mut() {
+            if *i > removed {
+                *i -= 1;
+            }
         }
+
+        Some(self.entries.remove(removed).1)
+    }
+
+    pub fn iter(&self) -> AttrIter<'_> {
+        AttrIter(self.entries.iter())
+    }
+}
+
+impl PartialEq for AttrMap {
+    fn eq(&self, other: &AttrMap) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl fmt::Debug for AttrMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for AttrMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (name, value) in self {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'a> Iterator for AttrIter<'a> {
+    type Item = (&'a String, &'a String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(name, value)| (name, value))
+    }
+}
+
+impl<'a> IntoIterator for &'a AttrMap {
+    type Item = (&'a String, &'a String);
+    type IntoIter = AttrIter<'a>;
+
+    fn into_iter(self) -> AttrIter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for AttrMap {
+    type Item = (String, String);
+    type IntoIter = vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
     }
 }
 
@@ -43,9 +805,205 @@ impl ElementData {
     }
 
     pub fn classes(&self) -> HashSet<&str> {
-        match self.attributes.get("class") {
-            Some(class_list) => class_list.split(" ").collect(),
-            None => HashSet::new(),
+        self.class_list().into_iter().collect()
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.class_list().contains(&class)
+    }
+
+    /// Add `class` unless it is already there. Like the other class-list
+    /// methods, this rewrites the `class` attribute as the classes in order,
+    /// without duplicates, separated by single spaces, and refuses a name
+    /// that is empty or holds white space.
+    pub fn add_class(&mut self, class: &str) -> Result<(), DomError> {
+        check_class(class)?;
+        let mut classes = self.class_list();
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+        let class_list = classes.join(" ");
+        self.attributes.insert("class".to_string(), class_list);
+        Ok(())
+    }
+
+    pub fn remove_class(&mut self, class: &str) -> Result<(), DomError> {
+        check_class(class)?;
+        // Nothing to rewrite on an element without the attribute.
+        if !self.attributes.contains_key("class") {
+            return Ok(());
         }
+        let mut classes = self.class_list();
+        classes.retain(|&name| name != class);
+        let class_list = classes.join(" ");
+        self.attributes.insert("class".to_string(), class_list);
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Add `class` if it is missing and remove it otherwise, returning
+    /// whether it is now present.
+    pub fn toggle_class(&mut self, class: &str) -> Result<bool, DomError> {
+        if self.has_class(class) {
+            self.remove_class(class)?;
+            Ok(false)
+        } else {
+            self.add_class(class)?;
+            Ok(true)
+        }
+    }
+
+    /// The classes in source order, with duplicates dropped.
+    fn class_list(&self) -> Vec<&str> {
+        let mut classes = Vec::new();
+        if let Some(class_list) = self.attributes.get("class") {
+            for class in class_list.split_ascii_whitespace() {
+                if !classes.contains(&class) {
+                    classes.push(class);
+                }
+            }
+        }
+
+        classes
+    }
+}
+
+fn check_class(class: &str) -> Result<(), DomError> {
+    if class.is_empty() || class.contains(|c: char| c.is_ascii_whitespace()) {
+        return Err(DomError::InvalidClass);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(class: &str) -> ElementData {
+        let mut attributes = AttrMap::new();
+        attributes.insert("class".to_string(), class.to_string());
+        ElementData { tag_name: "p".to_string(), attributes }
+    }
+
+    /// A root `div` holding `p#a` (with a `span` and some text), `p#b` and
+    /// `p#c`, and the ids of those nodes.
+    fn sample() -> (Document, [NodeId; 5]) {
+        let p = |id: &str, children| {
+            let mut attributes = AttrMap::new();
+            attributes.insert("id".to_string(), id.to_string());
+            Tree::elem("p".to_string(), attributes, children)
+        };
+        let a = p("a", vec![Tree::elem("span".to_string(), AttrMap::new(), Vec::new()), Tree::text("x".to_string())]);
+        let children = vec![a, p("b", Vec::new()), p("c", Vec::new())];
+        let document = Document::from_tree(Tree::elem("div".to_string(), AttrMap::new(), children));
+        let ids: Vec<_> = document.children(document.root()).collect();
+        let span = document.first_child(ids[0]).unwrap();
+        let text = document.next_sibling(span).unwrap();
+
+        (document, [ids[0], ids[1], ids[2], span, text])
+    }
+
+    /// Every link in the document, to check that nothing changed.
+    fn links(document: &Document) -> Vec<[Option<NodeId>; 5]> {
+        document.nodes.iter()
+            .map(|node| [node.parent, node.first_child, node.last_child, node.prev_sibling, node.next_sibling])
+            .collect()
+    }
+
+    /// The ids of the root's children, after checking that the links back
+    /// from the last child agree with the links forward from the first.
+    fn child_ids(document: &Document) -> Vec<&str> {
+        let root = document.root();
+        let forward: Vec<_> = document.children(root).collect();
+        let mut backward: Vec<_> = std::iter::successors(document.last_child(root), |&id| document.prev_sibling(id))
+            .collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert!(forward.iter().all(|&id| document.parent(id) == Some(root)));
+
+        forward.iter()
+            .map(|&id| match document[id].node_type {
+                NodeType::Element(ref elem) => elem.id().unwrap().as_str(),
+                _ => panic!("expected an element"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refused_mutations_leave_the_document_unchanged() {
+        let (mut document, [a, b, _, span, text]) = sample();
+        let root = document.root();
+        let detached = document.create_element("em".to_string(), AttrMap::new());
+        let before = links(&document);
+
+        assert_eq!(document.append_child(span, a), Err(DomError::Cycle));
+        assert_eq!(document.append_child(a, a), Err(DomError::Cycle));
+        assert_eq!(document.insert_before(span, root, None), Err(DomError::RootMoved));
+        assert_eq!(document.replace_child(root, root, b), Err(DomError::RootMoved));
+        assert_eq!(document.append_child(text, b), Err(DomError::LeafParent));
+        assert_eq!(document.replace_child(text, b, text), Err(DomError::NotAChild));
+        assert_eq!(document.insert_before(root, b, Some(span)), Err(DomError::NotAChild));
+        assert_eq!(document.insert_before(root, detached, Some(detached)), Err(DomError::NotAChild));
+        assert_eq!(document.remove_child(root, span), Err(DomError::NotAChild));
+        assert_eq!(document.replace_child(root, detached, span), Err(DomError::NotAChild));
+        assert_eq!(links(&document), before);
+    }
+
+    #[test]
+    fn moves_between_adjacent_siblings() {
+        let (mut document, [a, b, c, _, _]) = sample();
+        let root = document.root();
+        document.insert_before(root, b, Some(c)).unwrap();
+        document.insert_before(root, b, Some(b)).unwrap();
+        assert_eq!(child_ids(&document), ["a", "b", "c"]);
+        document.insert_before(root, c, Some(b)).unwrap();
+        assert_eq!(child_ids(&document), ["a", "c", "b"]);
+        document.insert_before(root, a, Some(b)).unwrap();
+        assert_eq!(child_ids(&document), ["c", "a", "b"]);
+        document.append_child(root, b).unwrap();
+        assert_eq!(child_ids(&document), ["c", "a", "b"]);
+
+        let (mut document, [a, b, c, _, _]) = sample();
+        let root = document.root();
+        // The new child right after the old one, then right before it.
+        document.replace_child(root, b, a).unwrap();
+        assert_eq!(child_ids(&document), ["b", "c"]);
+        document.replace_child(root, b, c).unwrap();
+        assert_eq!(child_ids(&document), ["b"]);
+        assert_eq!(document.parent(a), None);
+        assert_eq!(document.parent(c), None);
+        document.replace_child(root, a, b).unwrap();
+        document.insert_before(root, c, Some(a)).unwrap();
+        assert_eq!(child_ids(&document), ["c", "a"]);
+    }
+
+    #[test]
+    fn classes_split_on_any_white_space() {
+        let elem = element(" a\tb\n a  c ");
+        assert_eq!(elem.classes(), HashSet::from(["a", "b", "c"]));
+        assert!(elem.has_class("b"));
+        assert!(!elem.has_class(""));
+    }
+
+    #[test]
+    fn class_list_methods_rewrite_the_attribute() {
+        let mut elem = element(" a\tb  a ");
+        elem.add_class("c").unwrap();
+        assert_eq!(elem.attributes.get("class").unwrap(), "a b c");
+        elem.remove_class("a").unwrap();
+        assert_eq!(elem.attributes.get("class").unwrap(), "b c");
+        assert_eq!(elem.toggle_class("b"), Ok(false));
+        assert_eq!(elem.toggle_class("d"), Ok(true));
+        assert_eq!(elem.attributes.get("class").unwrap(), "c d");
+    }
+
+    #[test]
+    fn invalid_class_names_are_refused() {
+        let mut elem = element("a b");
+        assert_eq!(elem.add_class(""), Err(DomError::InvalidClass));
+        assert_eq!(elem.add_class("c d"), Err(DomError::InvalidClass));
+        assert_eq!(elem.remove_class("a b"), Err(DomError::InvalidClass));
+        assert_eq!(elem.toggle_class("x\ty"), Err(DomError::InvalidClass));
+        assert_eq!(elem.attributes.get("class").unwrap(), "a b");
+    }
+}
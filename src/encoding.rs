@@ -0,0 +1,286 @@
+// Character encoding detection and decoding for input documents.
+
+use std::char;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Also used for documents labelled latin-1 or ASCII, as browsers do.
+    Windows1252,
+}
+
+/// How far into the document to look for `<meta charset>`.
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Decode a document, picking the encoding from its byte order mark, then
+/// from a `<meta charset>` near the start, and otherwise from whether it is
+/// valid UTF-8.
+pub fn decode(bytes: &[u8]) -> (String, Encoding) {
+    let mut decoder = Decoder::new();
+    let text = decoder.decode(bytes, true);
+
+    (text, decoder.encoding().unwrap())
+}
+
+/// Decodes a document that arrives in chunks. The encoding is sniffed as in
+/// `decode`, but only from the first `PRESCAN_LENGTH` bytes, so no text comes
+/// out until that many have been fed or the input ends.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    encoding: Option<Encoding>,
+    /// Bytes held back: the start of the document while sniffing, then any
+    /// character split across chunks.
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// The sniffed encoding, once known.
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    /// Decode the next chunk. `last` marks the end of input, flushing any
+    /// truncated character as U+FFFD.
+    pub fn decode(&mut self, bytes: &[u8], last: bool) -> String {
+        self.pending.extend_from_slice(bytes);
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None if last || self.pending.len() >= PRESCAN_LENGTH => {
+                let (encoding, bom_length) = sniff(&self.pending);
+                self.pending.drain(..bom_length);
+                self.encoding = Some(encoding);
+                encoding
+            },
+            None => return String::new(),
+        };
+
+        let complete = if last { self.pending.len() } else { complete_length(&self.pending, encoding) };
+        let text = decode_with(&self.pending[..complete], encoding);
+        self.pending.drain(..complete);
+
+        text
+    }
+}
+
+/// Decode `bytes` as `encoding`, replacing malformed sequences with U+FFFD.
+pub fn decode_with(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        Encoding::Windows1252 => bytes.iter().map(|&byte| windows_1252_char(byte)).collect(),
+    }
+}
+
+/// The encoding for a label such as `"ISO-8859-1"`, per the labels browsers accept.
+pub fn from_label(label: &str) -> Option<Encoding> {
+    match &*label.trim().to_ascii_lowercase() {
+        "utf-8" | "utf8" | "unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" | "x-unicode20utf8" => {
+            Some(Encoding::Utf8)
+        },
+        "utf-16" | "utf-16le" | "ucs-2" | "unicode" | "csunicode" | "iso-10646-ucs-2" | "unicodefeff" => {
+            Some(Encoding::Utf16Le)
+        },
+        "utf-16be" | "unicodefffe" => Some(Encoding::Utf16Be),
+        "windows-1252" | "cp1252" | "x-cp1252" | "latin1" | "l1" | "iso-8859-1" | "iso8859-1"
+        | "iso88591" | "iso_8859-1" | "iso_8859-1:1987" | "iso-ir-100" | "ibm819" | "cp819"
+        | "csisolatin1" | "us-ascii" | "ascii" | "ansi_x3.4-1968" => Some(Encoding::Windows1252),
+        _ => None,
+    }
+}
+
+/// The windows-1252 character for a byte. Bytes 0x80..=0x9F hold
+/// punctuation and a few letters; the five unassigned ones map to the C1
+/// control of the same value.
+pub fn windows_1252_char(byte: u8) -> char {
+    match byte {
+        0x80 => '\u{20ac}', 0x82 => '\u{201a}', 0x83 => '\u{192}', 0x84 => '\u{201e}',
+        0x85 => '\u{2026}', 0x86 => '\u{2020}', 0x87 => '\u{2021}', 0x88 => '\u{2c6}',
+        0x89 => '\u{2030}', 0x8a => '\u{160}', 0x8b => '\u{2039}', 0x8c => '\u{152}',
+        0x8e => '\u{17d}', 0x91 => '\u{2018}', 0x92 => '\u{2019}', 0x93 => '\u{201c}',
+        0x94 => '\u{201d}', 0x95 => '\u{2022}', 0x96 => '\u{2013}', 0x97 => '\u{2014}',
+        0x98 => '\u{2dc}', 0x99 => '\u{2122}', 0x9a => '\u{161}', 0x9b => '\u{203a}',
+        0x9c => '\u{153}', 0x9e => '\u{17e}', 0x9f => '\u{178}',
+        byte => byte as char,
+    }
+}
+
+fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        Some((Encoding::Utf8, 3))
+    } else if bytes.starts_with(&[0xff, 0xfe]) {
+        Some((Encoding::Utf16Le, 2))
+    } else if bytes.starts_with(&[0xfe, 0xff]) {
+        Some((Encoding::Utf16Be, 2))
+    } else {
+        None
+    }
+}
+
+/// The encoding of a document starting with `bytes`, and the length of its
+/// byte order mark.
+fn sniff(bytes: &[u8]) -> (Encoding, usize) {
+    if let Some(bom) = sniff_bom(bytes) {
+        return bom;
+    }
+
+    let encoding = prescan_meta(bytes).unwrap_or_else(|| {
+        let complete = complete_length(bytes, Encoding::Utf8);
+        if std::str::from_utf8(&bytes[..complete]).is_ok() { Encoding::Utf8 } else { Encoding::Windows1252 }
+    });
+
+    (encoding, 0)
+}
+
+/// How much of `bytes` can be decoded without splitting a character that
+/// may continue in the next chunk.
+fn complete_length(bytes: &[u8], encoding: Encoding) -> usize {
+    match encoding {
+        Encoding::Utf8 => {
+            // A truncated sequence starts at most three bytes from the end.
+            for back in 1..=bytes.len().min(3) {
+                let byte = bytes[bytes.len() - back];
+                if byte & 0xc0 != 0x80 {
+                    let length = match byte {
+                        0xc0..=0xdf => 2,
+                        0xe0..=0xef => 3,
+                        0xf0..=0xf7 => 4,
+                        _ => 1,
+                    };
+                    return if length > back { bytes.len() - back } else { bytes.len() };
+                }
+            }
+            bytes.len()
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let even = bytes.len() - bytes.len() % 2;
+            if even == 0 {
+                return 0;
+            }
+            let pair = [bytes[even - 2], bytes[even - 1]];
+            let unit = if encoding == Encoding::Utf16Le { u16::from_le_bytes(pair) } else { u16::from_be_bytes(pair) };
+            // Keep a high surrogate with the low one that follows it.
+            if (0xd800..0xdc00).contains(&unit) { even - 2 } else { even }
+        },
+        Encoding::Windows1252 => bytes.len(),
+    }
+}
+
+/// Look for a charset in the `<meta>` tags at the start of the document,
+/// either `<meta charset="...">` or the `charset=` in an `http-equiv`
+/// content type.
+fn prescan_meta(bytes: &[u8]) -> Option<Encoding> {
+    let head = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    // Only ASCII matters here, so other bytes can be anything.
+    let head: String = head.iter().map(|&byte| (byte as char).to_ascii_lowercase()).collect();
+
+    let mut rest = &*head;
+    while let Some(start) = rest.find("<meta") {
+        let tag = &rest[start..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        if let Some(encoding) = charset_in(tag).and_then(from_label) {
+            // A UTF-16 label can't be right for a document we just read as ASCII.
+            return Some(match encoding {
+                Encoding::Utf16Le | Encoding::Utf16Be => Encoding::Utf8,
+                encoding => encoding,
+            });
+        }
+        rest = &rest[start + "<meta".len()..];
+    }
+
+    return None;
+}
+
+fn charset_in(tag: &str) -> Option<&str> {
+    let value = &tag[tag.find("charset")? + "charset".len()..];
+    let value = value.trim_start().strip_prefix('=')?.trim_start();
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value.find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+        .unwrap_or(value.len());
+
+    Some(&value[..end])
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+    let mut text: String = char::decode_utf16(units)
+        .map(|unit| unit.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    // A dangling odd byte is a truncated code unit.
+    if bytes.len() % 2 == 1 {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!(decode(b"\xef\xbb\xbfh\xc3\xa9"), ("hé".to_string(), Encoding::Utf8));
+        assert_eq!(decode(b"\xff\xfeh\x00\xe9\x00"), ("hé".to_string(), Encoding::Utf16Le));
+        assert_eq!(decode(b"\xfe\xff\x00h\x00\xe9"), ("hé".to_string(), Encoding::Utf16Be));
+        // A byte order mark wins over `<meta charset>`.
+        assert_eq!(decode(b"\xef\xbb\xbf<meta charset=latin1>\xc3\xa9").1, Encoding::Utf8);
+    }
+
+    #[test]
+    fn meta_prescan() {
+        let (text, encoding) = decode(b"<meta charset=\"windows-1252\"><p>caf\xe9");
+        assert_eq!((&*text, encoding), ("<meta charset=\"windows-1252\"><p>caf\u{e9}", Encoding::Windows1252));
+
+        let source = b"<META HTTP-EQUIV='Content-Type' CONTENT='text/html; charset=ISO-8859-1'>\x80";
+        let (text, encoding) = decode(source);
+        assert_eq!(encoding, Encoding::Windows1252);
+        assert!(text.ends_with(">\u{20ac}"));
+
+        // A meta tag past the prescan is not seen.
+        let late = format!("{}<meta charset=latin1>", " ".repeat(PRESCAN_LENGTH));
+        assert_eq!(decode(late.as_bytes()).1, Encoding::Utf8);
+    }
+
+    #[test]
+    fn utf_16_labels_mean_utf_8() {
+        assert_eq!(decode(b"<meta charset=utf-16>caf\xc3\xa9").1, Encoding::Utf8);
+        assert_eq!(decode(b"<meta charset='UTF-16BE'>caf\xc3\xa9").1, Encoding::Utf8);
+    }
+
+    #[test]
+    fn invalid_utf_8_falls_back_to_windows_1252() {
+        assert_eq!(decode(b"caf\xc3\xa9"), ("café".to_string(), Encoding::Utf8));
+        assert_eq!(decode(b"caf\xe9 \x93q\x94"), ("caf\u{e9} \u{201c}q\u{201d}".to_string(), Encoding::Windows1252));
+        // A character cut off at the end of the prescan is not invalid.
+        let mut cut = vec![b' '; PRESCAN_LENGTH - 1];
+        cut.extend_from_slice("é".as_bytes());
+        assert_eq!(decode(&cut).1, Encoding::Utf8);
+    }
+
+    #[test]
+    fn characters_split_across_chunks() {
+        let mut decoder = Decoder::new();
+        let padding = " ".repeat(PRESCAN_LENGTH);
+        assert_eq!(decoder.decode(padding.as_bytes(), false), padding);
+        assert_eq!(decoder.decode(b"a\xe2", false), "a");
+        assert_eq!(decoder.decode(b"\x82", false), "");
+        assert_eq!(decoder.decode(b"\xac\xf0\x9f", false), "\u{20ac}");
+        assert_eq!(decoder.decode(b"\x98\x80", false), "\u{1f600}");
+        // Cut off at the end of the input.
+        assert_eq!(decoder.decode(b"b\xc3", true), "b\u{fffd}");
+
+        let mut decoder = Decoder::new();
+        let padding = "a\x00".repeat(PRESCAN_LENGTH / 2);
+        assert_eq!(decoder.decode(&[&b"\xff\xfe"[..], padding.as_bytes()].concat(), false), "a".repeat(PRESCAN_LENGTH / 2));
+        assert_eq!(decoder.decode(b"\x3d", false), "");
+        assert_eq!(decoder.decode(b"\xd8\x00", false), "");
+        assert_eq!(decoder.decode(b"\xde", true), "\u{1f600}");
+    }
+}
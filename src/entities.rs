@@ -0,0 +1,201 @@
+// HTML character reference decoding.
+
+use super::encoding;
+
+/// Decode the character references in `text`. Inside attribute values a
+/// semicolon-less reference followed by `=` or an alphanumeric is left alone,
+/// so query strings like `?a=1&copy=2` survive.
+pub fn decode(text: &str, in_attribute: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        match decode_reference(rest, in_attribute) {
+            Some((c, consumed)) => {
+                result.push(c);
+                rest = &rest[consumed..];
+            },
+            None => result.push('&'),
+        }
+    }
+    result.push_str(rest);
+
+    return result;
+}
+
+/// Decode the reference that follows an `&`, returning the character and
+/// how many bytes of `s` it used.
+fn decode_reference(s: &str, in_attribute: bool) -> Option<(char, usize)> {
+    if let Some(numeric) = s.strip_prefix('#') {
+        return decode_numeric(numeric).map(|(c, consumed)| (c, consumed + 1));
+    }
+
+    let name_len = s.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(s.len());
+    let name = &s[..name_len];
+    if s[name_len..].starts_with(';') {
+        if let Some(c) = lookup(NAMED, name).or_else(|| lookup(LEGACY, name)) {
+            return Some((c, name_len + 1));
+        }
+    }
+
+    // Without a semicolon only the legacy names count, longest match first,
+    // so `&notit;` is `¬it;`.
+    let (c, len) = (1..=name_len).rev()
+        .find_map(|len| lookup(LEGACY, &name[..len]).map(|c| (c, len)))?;
+    if in_attribute {
+        if let Some(next) = s[len..].chars().next() {
+            if next == '=' || next.is_ascii_alphanumeric() {
+                return None;
+            }
+        }
+    }
+
+    Some((c, len))
+}
+
+fn decode_numeric(s: &str) -> Option<(char, usize)> {
+    let (radix, prefix) = if s.starts_with('x') || s.starts_with('X') { (16, 1) } else { (10, 0) };
+    let digits = &s[prefix..];
+    let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+    if len == 0 {
+        return None;
+    }
+
+    // Overflowing values are out of range like any other.
+    let value = u32::from_str_radix(&digits[..len], radix).unwrap_or(u32::MAX);
+    let mut consumed = prefix + len;
+    if s[consumed..].starts_with(';') {
+        consumed += 1;
+    }
+
+    Some((numeric_char(value), consumed))
+}
+
+/// The character for a numeric reference: NUL, surrogates and out of range
+/// values become U+FFFD, and the C1 controls are read as windows-1252.
+fn numeric_char(value: u32) -> char {
+    match value {
+        0 => '\u{fffd}',
+        0x80..=0x9f => encoding::windows_1252_char(value as u8),
+        _ => std::char::from_u32(value).unwrap_or('\u{fffd}'),
+    }
+}
+
+fn lookup(table: &[(&str, char)], name: &str) -> Option<char> {
+    table.binary_search_by_key(&name, |&(entry, _)| entry).ok().map(|index| table[index].1)
+}
+
+// Both tables are sorted by name for `lookup`.
+/// Named references that may also appear without their semicolon.
+const LEGACY: &[(&str, char)] = &[
+    ("AElig", '\u{c6}'), ("AMP", '&'), ("Aacute", '\u{c1}'), ("Acirc", '\u{c2}'),
+    ("Agrave", '\u{c0}'), ("Aring", '\u{c5}'), ("Atilde", '\u{c3}'), ("Auml", '\u{c4}'),
+    ("COPY", '\u{a9}'), ("Ccedil", '\u{c7}'), ("ETH", '\u{d0}'), ("Eacute", '\u{c9}'),
+    ("Ecirc", '\u{ca}'), ("Egrave", '\u{c8}'), ("Euml", '\u{cb}'), ("GT", '>'),
+    ("Iacute", '\u{cd}'), ("Icirc", '\u{ce}'), ("Igrave", '\u{cc}'), ("Iuml", '\u{cf}'),
+    ("LT", '<'), ("Ntilde", '\u{d1}'), ("Oacute", '\u{d3}'), ("Ocirc", '\u{d4}'),
+    ("Ograve", '\u{d2}'), ("Oslash", '\u{d8}'), ("Otilde", '\u{d5}'), ("Ouml", '\u{d6}'),
+    ("QUOT", '"'), ("REG", '\u{ae}'), ("THORN", '\u{de}'), ("Uacute", '\u{da}'),
+    ("Ucirc", '\u{db}'), ("Ugrave", '\u{d9}'), ("Uuml", '\u{dc}'), ("Yacute", '\u{dd}'),
+    ("aacute", '\u{e1}'), ("acirc", '\u{e2}'), ("acute", '\u{b4}'), ("aelig", '\u{e6}'),
+    ("agrave", '\u{e0}'), ("amp", '&'), ("aring", '\u{e5}'), ("atilde", '\u{e3}'),
+    ("auml", '\u{e4}'), ("brvbar", '\u{a6}'), ("ccedil", '\u{e7}'), ("cedil", '\u{b8}'),
+    ("cent", '\u{a2}'), ("copy", '\u{a9}'), ("curren", '\u{a4}'), ("deg", '\u{b0}'),
+    ("divide", '\u{f7}'), ("eacute", '\u{e9}'), ("ecirc", '\u{ea}'), ("egrave", '\u{e8}'),
+    ("eth", '\u{f0}'), ("euml", '\u{eb}'), ("frac12", '\u{bd}'), ("frac14", '\u{bc}'),
+    ("frac34", '\u{be}'), ("gt", '>'), ("iacute", '\u{ed}'), ("icirc", '\u{ee}'),
+    ("iexcl", '\u{a1}'), ("igrave", '\u{ec}'), ("iquest", '\u{bf}'), ("iuml", '\u{ef}'),
+    ("laquo", '\u{ab}'), ("lt", '<'), ("macr", '\u{af}'), ("micro", '\u{b5}'), ("middot", '\u{b7}'),
+    ("nbsp", '\u{a0}'), ("not", '\u{ac}'), ("ntilde", '\u{f1}'), ("oacute", '\u{f3}'),
+    ("ocirc", '\u{f4}'), ("ograve", '\u{f2}'), ("ordf", '\u{aa}'), ("ordm", '\u{ba}'),
+    ("oslash", '\u{f8}'), ("otilde", '\u{f5}'), ("ouml", '\u{f6}'), ("para", '\u{b6}'),
+    ("plusmn", '\u{b1}'), ("pound", '\u{a3}'), ("quot", '"'), ("raquo", '\u{bb}'),
+    ("reg", '\u{ae}'), ("sect", '\u{a7}'), ("shy", '\u{ad}'), ("sup1", '\u{b9}'),
+    ("sup2", '\u{b2}'), ("sup3", '\u{b3}'), ("szlig", '\u{df}'), ("thorn", '\u{fe}'),
+    ("times", '\u{d7}'), ("uacute", '\u{fa}'), ("ucirc", '\u{fb}'), ("ugrave", '\u{f9}'),
+    ("uml", '\u{a8}'), ("uuml", '\u{fc}'), ("yacute", '\u{fd}'), ("yen", '\u{a5}'),
+    ("yuml", '\u{ff}'),
+];
+
+/// Named references that need their semicolon.
+const NAMED: &[(&str, char)] = &[
+    ("Alpha", '\u{391}'), ("Beta", '\u{392}'), ("Chi", '\u{3a7}'), ("Dagger", '\u{2021}'),
+    ("Delta", '\u{394}'), ("Epsilon", '\u{395}'), ("Eta", '\u{397}'), ("Gamma", '\u{393}'),
+    ("Iota", '\u{399}'), ("Kappa", '\u{39a}'), ("Lambda", '\u{39b}'), ("Mu", '\u{39c}'),
+    ("Nu", '\u{39d}'), ("OElig", '\u{152}'), ("Omega", '\u{3a9}'), ("Omicron", '\u{39f}'),
+    ("Phi", '\u{3a6}'), ("Pi", '\u{3a0}'), ("Prime", '\u{2033}'), ("Psi", '\u{3a8}'),
+    ("Rho", '\u{3a1}'), ("Scaron", '\u{160}'), ("Sigma", '\u{3a3}'), ("Tau", '\u{3a4}'),
+    ("Theta", '\u{398}'), ("Upsilon", '\u{3a5}'), ("Xi", '\u{39e}'), ("Yuml", '\u{178}'),
+    ("Zeta", '\u{396}'), ("alefsym", '\u{2135}'), ("alpha", '\u{3b1}'), ("and", '\u{2227}'),
+    ("ang", '\u{2220}'), ("apos", '\''), ("asymp", '\u{2248}'), ("bdquo", '\u{201e}'),
+    ("beta", '\u{3b2}'), ("bull", '\u{2022}'), ("cap", '\u{2229}'), ("chi", '\u{3c7}'),
+    ("circ", '\u{2c6}'), ("clubs", '\u{2663}'), ("cong", '\u{2245}'), ("crarr", '\u{21b5}'),
+    ("cup", '\u{222a}'), ("dArr", '\u{21d3}'), ("dagger", '\u{2020}'), ("darr", '\u{2193}'),
+    ("delta", '\u{3b4}'), ("diams", '\u{2666}'), ("empty", '\u{2205}'), ("emsp", '\u{2003}'),
+    ("ensp", '\u{2002}'), ("epsilon", '\u{3b5}'), ("equiv", '\u{2261}'), ("eta", '\u{3b7}'),
+    ("euro", '\u{20ac}'), ("exist", '\u{2203}'), ("fnof", '\u{192}'), ("forall", '\u{2200}'),
+    ("frasl", '\u{2044}'), ("gamma", '\u{3b3}'), ("ge", '\u{2265}'), ("hArr", '\u{21d4}'),
+    ("harr", '\u{2194}'), ("hearts", '\u{2665}'), ("hellip", '\u{2026}'), ("image", '\u{2111}'),
+    ("infin", '\u{221e}'), ("int", '\u{222b}'), ("iota", '\u{3b9}'), ("isin", '\u{2208}'),
+    ("kappa", '\u{3ba}'), ("lArr", '\u{21d0}'), ("lambda", '\u{3bb}'), ("lang", '\u{2329}'),
+    ("larr", '\u{2190}'), ("lceil", '\u{2308}'), ("ldquo", '\u{201c}'), ("le", '\u{2264}'),
+    ("lfloor", '\u{230a}'), ("lowast", '\u{2217}'), ("loz", '\u{25ca}'), ("lrm", '\u{200e}'),
+    ("lsaquo", '\u{2039}'), ("lsquo", '\u{2018}'), ("mdash", '\u{2014}'), ("minus", '\u{2212}'),
+    ("mu", '\u{3bc}'), ("nabla", '\u{2207}'), ("ndash", '\u{2013}'), ("ne", '\u{2260}'),
+    ("ni", '\u{220b}'), ("notin", '\u{2209}'), ("nsub", '\u{2284}'), ("nu", '\u{3bd}'),
+    ("oelig", '\u{153}'), ("oline", '\u{203e}'), ("omega", '\u{3c9}'), ("omicron", '\u{3bf}'),
+    ("oplus", '\u{2295}'), ("or", '\u{2228}'), ("otimes", '\u{2297}'), ("part", '\u{2202}'),
+    ("permil", '\u{2030}'), ("perp", '\u{22a5}'), ("phi", '\u{3c6}'), ("pi", '\u{3c0}'),
+    ("piv", '\u{3d6}'), ("prime", '\u{2032}'), ("prod", '\u{220f}'), ("prop", '\u{221d}'),
+    ("psi", '\u{3c8}'), ("rArr", '\u{21d2}'), ("radic", '\u{221a}'), ("rang", '\u{232a}'),
+    ("rarr", '\u{2192}'), ("rceil", '\u{2309}'), ("rdquo", '\u{201d}'), ("real", '\u{211c}'),
+    ("rfloor", '\u{230b}'), ("rho", '\u{3c1}'), ("rlm", '\u{200f}'), ("rsaquo", '\u{203a}'),
+    ("rsquo", '\u{2019}'), ("sbquo", '\u{201a}'), ("scaron", '\u{161}'), ("sdot", '\u{22c5}'),
+    ("sigma", '\u{3c3}'), ("sigmaf", '\u{3c2}'), ("sim", '\u{223c}'), ("spades", '\u{2660}'),
+    ("sub", '\u{2282}'), ("sube", '\u{2286}'), ("sum", '\u{2211}'), ("sup", '\u{2283}'),
+    ("supe", '\u{2287}'), ("tau", '\u{3c4}'), ("there4", '\u{2234}'), ("theta", '\u{3b8}'),
+    ("thetasym", '\u{3d1}'), ("thinsp", '\u{2009}'), ("tilde", '\u{2dc}'), ("trade", '\u{2122}'),
+    ("uArr", '\u{21d1}'), ("uarr", '\u{2191}'), ("upsih", '\u{3d2}'), ("upsilon", '\u{3c5}'),
+    ("weierp", '\u{2118}'), ("xi", '\u{3be}'), ("zeta", '\u{3b6}'), ("zwj", '\u{200d}'),
+    ("zwnj", '\u{200c}'),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_sorted() {
+        for table in [LEGACY, NAMED] {
+            assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn named_references() {
+        assert_eq!(decode("&notin; &notit; &not", false), "\u{2209} \u{ac}it; \u{ac}");
+        assert_eq!(decode("&amp;&AMP&lt;b&gt;", false), "&&<b>");
+        assert_eq!(decode("&bogus; & &;", false), "&bogus; & &;");
+    }
+
+    #[test]
+    fn legacy_references_in_attributes() {
+        // Followed by `=` or a letter, a reference without its semicolon is
+        // left alone in an attribute, so query strings survive.
+        assert_eq!(decode("?a=1&copy=2&copyx", true), "?a=1&copy=2&copyx");
+        assert_eq!(decode("?a=1&copy=2&copyx", false), "?a=1\u{a9}=2\u{a9}x");
+        assert_eq!(decode("&copy;=&copy.", true), "\u{a9}=\u{a9}.");
+    }
+
+    #[test]
+    fn numeric_references() {
+        assert_eq!(decode("&#65;&#x42;&#X43&#x1F600;", false), "ABC\u{1f600}");
+        // C1 controls are read as windows-1252.
+        assert_eq!(decode("&#128;&#x9F;", false), "\u{20ac}\u{178}");
+        // NUL, surrogates, out of range and overflowing values.
+        assert_eq!(decode("&#0;&#xD800;&#x110000;&#99999999999999;", false), "\u{fffd}".repeat(4));
+        // No digits is no reference.
+        assert_eq!(decode("&#x;&#;&#xg;", false), "&#x;&#;&#xg;");
+    }
+}
@@ -1,32 +1,303 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
 
 use super::dom;
+use super::encoding;
+use super::entities;
+use super::tree_builder::TreeBuilder;
 
-struct Parser {
-    pos: usize,
+/// A malformed-markup error, located at the byte offset where it was found.
+/// Parsing recovers from every error the way browsers do, so errors are
+/// reported alongside the document rather than instead of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Elements that never have content or an end tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+pub fn is_void_element(tag_name: &str) -> bool {
+    VOID_ELEMENTS.contains(&tag_name)
+}
+
+/// Elements whose content is text up to their end tag, so `a > b` in a
+/// stylesheet is not markup.
+const RAW_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "noscript", "script", "style", "xmp"];
+
+/// Whether the text inside `tag_name` is read literally, without markup or
+/// character references.
+pub fn is_raw_text_element(tag_name: &str) -> bool {
+    RAW_TEXT_ELEMENTS.contains(&tag_name)
+}
+
+/// Raw text elements whose character references are still decoded.
+const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
+
+/// A unit of markup produced by the tokenizer and consumed by the tree builder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    StartTag { name: String, attributes: dom::AttrMap, self_closing: bool },
+    EndTag { name: String },
+    Text(String),
+    Comment(String),
+    Doctype(dom::Doctype),
+    Eof,
+}
+
+/// Splits markup into tokens as it arrives. Input is fed in chunks of bytes
+/// and each call returns the tokens those bytes completed; a token cut off
+/// at the end of a chunk waits for the next one.
+pub struct Tokenizer {
+    /// The input not yet discarded, starting at byte `base` of the document.
     input: String,
+    base: usize,
+    pos: usize,
+    /// Set after the start tag of a raw text element, until its content is read.
+    raw_text: Option<String>,
+    /// Line and column bookkeeping, kept at or before `pos`.
+    position: dom::SourcePosition,
+    decoder: encoding::Decoder,
+    /// Whether all input has been fed, so reaching its end ends the token.
+    finished: bool,
+    /// Set when reading a token looked at the end of the input, whose
+    /// outcome could change once more arrives.
+    needs_input: Cell<bool>,
+    /// The last search that ran out of input, so reading the same token
+    /// again after more arrives does not search the same text twice.
+    scan: Option<Scan>,
+    /// A tag cut off at the end of the input, kept so reading it again
+    /// resumes after the attributes already read.
+    partial_tag: Option<PartialTag>,
+    errors: Vec<ParseError>,
 }
 
-impl Parser {
+/// A search for `pattern` from document offset `from` that found nothing
+/// it was looking for before document offset `to`. For `find_char` the
+/// pattern names the characters searched for instead.
+#[derive(Clone, Copy)]
+struct Scan {
+    from: usize,
+    pattern: &'static str,
+    to: usize,
+}
+
+/// The part of a tag read before the input ran out.
+struct PartialTag {
+    /// Document offset of the `<`.
+    from: usize,
+    name: String,
+    attributes: dom::AttrMap,
+    /// Document offset of the first attribute not yet read.
+    resume: usize,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Tokenizer {
+        Tokenizer::new()
+    }
+}
+
+impl Tokenizer {
+    pub fn new() -> Tokenizer {
+        Tokenizer {
+            input: String::new(),
+            base: 0,
+            pos: 0,
+            raw_text: None,
+            position: dom::SourcePosition::start(),
+            decoder: encoding::Decoder::new(),
+            finished: false,
+            needs_input: Cell::new(false),
+            scan: None,
+            partial_tag: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Feed the next chunk of the document, returning the tokens it completed.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<(Token, dom::SourceSpan)> {
+        let text = self.decoder.decode(bytes, false);
+        self.push_input(&text);
+
+        self.read_tokens()
+    }
+
+    /// Mark the end of the document, returning the remaining tokens, the
+    /// last of which is `Token::Eof`.
+    pub fn finish(&mut self) -> Vec<(Token, dom::SourceSpan)> {
+        let text = self.decoder.decode(&[], true);
+        self.push_input(&text);
+        self.finished = true;
+
+        self.read_tokens()
+    }
+
+    fn push_input(&mut self, text: &str) {
+        // Everything before `pos` has been tokenized.
+        self.input.drain(..self.pos);
+        self.base += self.pos;
+        self.pos = 0;
+        self.input.push_str(text);
+    }
+
+    /// The errors found in the tokens returned so far.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    fn read_tokens(&mut self) -> Vec<(Token, dom::SourceSpan)> {
+        let mut tokens = Vec::new();
+        while let Some((token, span)) = self.next_token() {
+            let done = token == Token::Eof;
+            tokens.push((token, span));
+            if done {
+                break;
+            }
+        }
+
+        tokens
+    }
+
     // 通用函数
     fn next_char(&self) -> char {
         self.input[self.pos..].chars().next().unwrap()
     }
 
+    /// Note that a test on the input left is undecided while the input could
+    /// still grow to change its answer.
+    fn undecided(&self) {
+        self.needs_input.set(true);
+    }
+
     fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+        let rest = &self.input[self.pos..];
+        if rest.len() < s.len() && s.starts_with(rest) {
+            self.undecided();
+        }
+
+        rest.starts_with(s)
+    }
+
+    fn starts_with_ignore_case(&self, s: &str) -> bool {
+        let rest = &self.input[self.pos..];
+        if rest.len() < s.len() && s.get(..rest.len()).is_some_and(|head| head.eq_ignore_ascii_case(rest)) {
+            self.undecided();
+        }
+
+        rest.get(..s.len()).is_some_and(|head| head.eq_ignore_ascii_case(s))
+    }
+
+    /// Whether the token being read ran into the end of input that is not
+    /// the end of the document, so it will be read again and any text
+    /// collected so far can be dropped.
+    fn waiting(&self) -> bool {
+        self.needs_input.get() && !self.finished
+    }
+
+    /// The index of `pattern` in the input left, from `self.pos`. A search
+    /// from the same place that ran out of input before picks up where it
+    /// stopped, which keeps a long token fed in many chunks linear.
+    fn find(&mut self, pattern: &'static str) -> Option<usize> {
+        let from = self.base + self.pos;
+        let rest = &self.input[self.pos..];
+        let skip = match self.scan {
+            Some(scan) if scan.from == from && scan.pattern == pattern => scan.to - from,
+            _ => 0,
+        };
+        match rest[skip..].find(pattern) {
+            Some(index) => Some(skip + index),
+            None => {
+                self.undecided();
+                // A match may begin in the last few bytes and end in the next chunk.
+                let mut to = rest.len().saturating_sub(pattern.len() - 1).max(skip);
+                while !rest.is_char_boundary(to) {
+                    to -= 1;
+                }
+                self.scan = Some(Scan { from, pattern, to: from + to });
+                None
+            },
+        }
+    }
+
+    /// The index of the first character passing `test` in the input left,
+    /// resuming like `find`; `what` names the search.
+    fn find_char<F>(&mut self, what: &'static str, test: F) -> Option<usize>
+    where F: Fn(char) -> bool {
+        let from = self.base + self.pos;
+        let rest = &self.input[self.pos..];
+        let skip = match self.scan {
+            Some(scan) if scan.from == from && scan.pattern == what => scan.to - from,
+            _ => 0,
+        };
+        match rest[skip..].find(test) {
+            Some(index) => Some(skip + index),
+            None => {
+                self.undecided();
+                self.scan = Some(Scan { from, pattern: what, to: from + rest.len() });
+                None
+            },
+        }
+    }
+
+    /// Consume characters up to the first one passing `end`, which may be
+    /// cut off by the end of input like `consume_until`.
+    fn consume_until_char<F>(&mut self, what: &'static str, end: F) -> String
+    where F: Fn(char) -> bool {
+        let length = match self.find_char(what, end) {
+            Some(index) => index,
+            None if self.waiting() => return String::new(),
+            None => self.input.len() - self.pos,
+        };
+        let data = self.input[self.pos..self.pos + length].to_string();
+        self.pos += length;
+
+        data
+    }
+
+    /// Consume up to and including `end`, returning what came before it.
+    /// An unterminated construct runs to the end of input.
+    fn consume_until(&mut self, end: &'static str) -> String {
+        let (length, consumed) = match self.find(end) {
+            Some(index) => (index, index + end.len()),
+            None if self.waiting() => return String::new(),
+            None => (self.input.len() - self.pos, self.input.len() - self.pos),
+        };
+        let data = self.input[self.pos..self.pos + length].to_string();
+        self.pos += consumed;
+
+        data
     }
 
     fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+        let eof = self.pos >= self.input.len();
+        if eof {
+            self.undecided();
+        }
+
+        eof
     }
 
     fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let ( _, crt_char ) = iter.next().unwrap();
-        let ( next_pos, _ ) = iter.next().unwrap_or((1, ' '));
-
-        self.pos += next_pos;
+        let crt_char = self.next_char();
+        self.pos += crt_char.len_utf8();
 
         crt_char
     }
@@ -45,90 +316,564 @@ impl Parser {
         self.consume_while(char::is_whitespace);
     }
 
+    /// Tag names run up to whitespace, `/` or `>`, so custom elements like
+    /// `my-element` and prefixed names like `svg:rect` are read whole. They
+    /// are ASCII case-insensitive, so they are lowercased here.
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'...'z' | 'A'...'Z' | '0'...'9' => true,
-            _ => false,
-        })
+        self.consume_until_char("tag name", |c| c == '/' || c == '>' || c.is_whitespace())
+            .to_ascii_lowercase()
+    }
+
+    // 错误处理
+    fn error_at(&mut self, pos: usize, message: String) {
+        let position = self.position.advanced_by(&self.input[self.position.offset - self.base..pos]);
+        self.errors.push(ParseError { message, offset: position.offset, line: position.line, column: position.column });
+    }
+
+    fn position(&mut self) -> dom::SourcePosition {
+        self.position = self.position.advanced_by(&self.input[self.position.offset - self.base..self.pos]);
+        self.position
+    }
+
+    // 词法分析
+    /// The next token and the source text it was read from, or `None` if the
+    /// input so far ends partway through it.
+    fn next_token(&mut self) -> Option<(Token, dom::SourceSpan)> {
+        let (pos, raw_text, position, errors) = (self.pos, self.raw_text.clone(), self.position, self.errors.len());
+        self.needs_input.set(false);
+        let result = self.read_next_token();
+        if self.needs_input.get() && !self.finished {
+            // Start over once more input arrives.
+            self.pos = pos;
+            self.raw_text = raw_text;
+            self.position = position;
+            self.errors.truncate(errors);
+            return None;
+        }
+
+        Some(result)
+    }
+
+    fn read_next_token(&mut self) -> (Token, dom::SourceSpan) {
+        if let Some(tag_name) = self.raw_text.take() {
+            let start = self.position();
+            let text = self.parse_raw_text(&tag_name);
+            if self.waiting() {
+                return (Token::Text(text), dom::SourceSpan { start, end: start });
+            }
+            if !text.is_empty() {
+                return (Token::Text(text), dom::SourceSpan { start, end: self.position() });
+            }
+        }
+
+        // `</>` is dropped entirely.
+        while self.starts_with("</>") {
+            self.pos += 3;
+        }
+
+        let start = self.position();
+        let token = self.read_token();
+        if self.waiting() {
+            // Thrown away by `next_token`, so skip counting lines.
+            return (token, dom::SourceSpan { start, end: start });
+        }
+
+        (token, dom::SourceSpan { start, end: self.position() })
+    }
+
+    fn read_token(&mut self) -> Token {
+        if self.eof() {
+            return Token::Eof;
+        }
+
+        if self.starts_with("<!--") {
+            self.parse_comment()
+        } else if self.starts_with_ignore_case("<!doctype") {
+            self.parse_doctype()
+        } else if self.starts_with("<!") || self.starts_with("<?") {
+            // Processing instructions and other markup declarations are
+            // bogus comments in HTML.
+            self.consume_char();
+            if self.next_char() == '!' {
+                self.consume_char();
+            }
+            Token::Comment(self.consume_until(">"))
+        } else if self.starts_with("</") && self.is_tag_name_start(2) {
+            self.parse_end_tag()
+        } else if self.starts_with("</") {
+            self.pos += 2;
+            Token::Comment(self.consume_until(">"))
+        } else if self.starts_with("<") && self.is_tag_name_start(1) {
+            self.parse_start_tag()
+        } else {
+            self.parse_text()
+        }
+    }
+
+    /// Whether a tag name begins `offset` bytes past the current position;
+    /// a `<` not followed by one is literal text, as in browsers.
+    fn is_tag_name_start(&self, offset: usize) -> bool {
+        match self.input[self.pos + offset..].chars().next() {
+            Some(c) => c.is_ascii_alphabetic(),
+            None => {
+                self.undecided();
+                false
+            },
+        }
+    }
+
+    fn parse_text(&mut self) -> Token {
+        let mut text = String::new();
+        if self.starts_with("<") {
+            text.push(self.consume_char());
+        }
+        let end = match self.find("<") {
+            Some(index) => self.pos + index,
+            None if self.waiting() => return Token::Text(text),
+            None => self.input.len(),
+        };
+        text.push_str(&self.input[self.pos..end]);
+        self.pos = end;
+
+        Token::Text(entities::decode(&text, false))
+    }
+
+    /// Read everything up to `</tag_name`, decoding references only for
+    /// escapable raw text.
+    fn parse_raw_text(&mut self, tag_name: &str) -> String {
+        let start = self.pos;
+        // Pick up where an earlier read of this text stopped; the scan
+        // records how far it is known to hold no end tag.
+        let mut from = match self.scan {
+            Some(scan) if scan.from == self.base + start && scan.pattern == "</" => scan.to - self.base,
+            _ => start,
+        };
+        loop {
+            match self.input[from..].find("</") {
+                Some(index) => {
+                    self.pos = from + index;
+                    if self.at_end_tag(tag_name) {
+                        break;
+                    }
+                    if self.waiting() {
+                        // `</` and part of a tag name; look at it again later.
+                        self.scan = Some(Scan { from: self.base + start, pattern: "</", to: self.base + self.pos });
+                        return String::new();
+                    }
+                    from = self.pos + 2;
+                },
+                None => {
+                    self.undecided();
+                    if self.waiting() {
+                        // A `<` at the very end may start the end tag.
+                        let mut to = self.input.len().saturating_sub(1).max(from);
+                        while !self.input.is_char_boundary(to) {
+                            to -= 1;
+                        }
+                        self.scan = Some(Scan { from: self.base + start, pattern: "</", to: self.base + to });
+                        return String::new();
+                    }
+                    self.pos = self.input.len();
+                    break;
+                },
+            }
+        }
+
+        let text = &self.input[start..self.pos];
+        if ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&tag_name) {
+            entities::decode(text, false)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn at_end_tag(&self, tag_name: &str) -> bool {
+        let rest = &self.input[self.pos..];
+        let name_end = 2 + tag_name.len();
+        if rest.len() <= name_end {
+            self.undecided();
+        }
+        rest.starts_with("</")
+            && rest.get(2..name_end).is_some_and(|name| name.eq_ignore_ascii_case(tag_name))
+            && rest[name_end..].chars().next()
+                .is_some_and(|c| c == '>' || c == '/' || c.is_whitespace())
+    }
+
+    fn parse_comment(&mut self) -> Token {
+        self.pos += "<!--".len();
+        // `<!-->` and `<!--->` are empty comments.
+        for abrupt_end in &[">", "->"] {
+            if self.starts_with(abrupt_end) {
+                self.pos += abrupt_end.len();
+                return Token::Comment(String::new());
+            }
+        }
+
+        Token::Comment(self.consume_until("-->"))
+    }
+
+    fn parse_doctype(&mut self) -> Token {
+        self.pos += "<!doctype".len();
+        self.consume_whitespace();
+        let name = self.consume_while(|c| c != '>' && !c.is_whitespace()).to_ascii_lowercase();
+        self.consume_whitespace();
+
+        let mut public_id = None;
+        let mut system_id = None;
+        if self.starts_with_ignore_case("public") {
+            self.pos += "public".len();
+            self.consume_whitespace();
+            public_id = self.parse_quoted();
+            self.consume_whitespace();
+            system_id = self.parse_quoted();
+        } else if self.starts_with_ignore_case("system") {
+            self.pos += "system".len();
+            self.consume_whitespace();
+            system_id = self.parse_quoted();
+        }
+        self.consume_until(">");
+
+        Token::Doctype(dom::Doctype { name, public_id, system_id })
     }
 
-    // 解析 Dom
-    fn parse_node(&mut self) -> dom::Node {
+    fn parse_quoted(&mut self) -> Option<String> {
+        if self.eof() {
+            return None;
+        }
+
         match self.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text(),
+            quote @ '"' | quote @ '\'' => {
+                self.consume_char();
+                let value = self.consume_while(|c| c != quote && c != '>');
+                if !self.eof() && self.next_char() == quote {
+                    self.consume_char();
+                }
+                Some(value)
+            },
+            _ => None,
         }
     }
 
-    fn parse_text(&mut self) -> dom::Node {
-        dom::Node::text(self.consume_while(|c| c != '<'))
+    fn parse_start_tag(&mut self) -> Token {
+        let (name, attributes) = match self.parse_tag("<") {
+            Some(tag) => tag,
+            None => return Token::Eof,
+        };
+        let self_closing = self.starts_with("/>");
+        if self_closing {
+            self.consume_char();
+        }
+        self.consume_char();
+
+        let is_raw_text = RAW_TEXT_ELEMENTS.contains(&&*name) || ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&&*name);
+        if is_raw_text && !self_closing {
+            self.raw_text = Some(name.clone());
+        }
+
+        Token::StartTag { name, attributes, self_closing }
     }
 
-    fn parse_element(&mut self) -> dom::Node {
-        // 开标签
-        assert_eq!(self.consume_char(), '<');
-        let tag_name = self.parse_tag_name();
-        let attrs = self.parse_attributes();
-        assert_eq!(self.consume_char(), '>');
+    fn parse_end_tag(&mut self) -> Token {
+        // Attributes and a trailing `/` are read as in a start tag and then
+        // ignored, as in browsers.
+        let name = match self.parse_tag("</") {
+            Some((name, _)) => name,
+            None => return Token::Eof,
+        };
+        if self.starts_with("/>") {
+            self.consume_char();
+        }
+        self.consume_char();
+
+        Token::EndTag { name }
+    }
 
-        // Contents
-        let children = self.parse_nodes();
+    /// The name and attributes of the tag opened by `open` at the current
+    /// position, stopping before its `>` or `/>`. A tag cut off by the end
+    /// of input keeps the attributes read so far, so reading it again after
+    /// more arrives starts from the first attribute not yet read. At the end
+    /// of the document the tag is dropped instead, as in browsers, and `None`
+    /// is returned either way.
+    fn parse_tag(&mut self, open: &str) -> Option<(String, dom::AttrMap)> {
+        let from = self.base + self.pos;
+        let (name, mut attributes) = match self.partial_tag.take() {
+            Some(tag) if tag.from == from => {
+                self.pos = tag.resume - self.base;
+                (tag.name, tag.attributes)
+            },
+            _ => {
+                self.pos += open.len();
+                (self.parse_tag_name(), dom::AttrMap::new())
+            },
+        };
+        if self.waiting() {
+            // Cut off in the name, so there is nothing to keep.
+            return None;
+        }
 
-        // 闭标签
-        assert_eq!(self.consume_char(), '<');
-        assert_eq!(self.consume_char(), '/');
-        assert_eq!(self.parse_tag_name(), tag_name);
-        assert_eq!(self.consume_char(), '>');
+        let resume = self.parse_attributes(&mut attributes);
+        if self.waiting() {
+            self.partial_tag = Some(PartialTag { from, name, attributes, resume: self.base + resume });
+            return None;
+        }
+        if self.eof() {
+            self.error_at(from - self.base, "unexpected end of input in tag".to_string());
+            return None;
+        }
 
-        return dom::Node::elem(tag_name, attrs, children);
+        Some((name, attributes))
     }
 
     fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_tag_name();
-        assert_eq!(self.consume_char(), '=');
-        assert_eq!(self.consume_char(), '"');
-        let value = self.parse_tag_name();
-        assert_eq!(self.consume_char(), '"');
+        let name = self.parse_attr_name();
+
+        // 无值属性, 如 <input disabled>
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '=' {
+            return (name, String::new());
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let value = self.parse_attr_value();
 
         return (name, value);
     }
 
-    fn parse_attributes(&mut self) -> dom::AttrMap {
-        let mut attributes = HashMap::new();
-        loop {
-            self.consume_whitespace();
-            if self.next_char() == '>' {
-                break;
-            }
-            let (name, value) = self.parse_attr();
-            attributes.insert(name, value);
+    /// Attribute names run up to whitespace, `=`, `/` or `>`, so `data-foo`
+    /// and `xml:lang` are read whole. Like tag names they are lowercased;
+    /// values keep their case. A stray quote, as in `<a href="x"">`, is
+    /// part of the name, as in browsers.
+    fn parse_attr_name(&mut self) -> String {
+        let mut name = String::new();
+        // A leading `=` belongs to the name rather than starting a value.
+        if !self.eof() && self.next_char() == '=' {
+            name.push(self.consume_char());
         }
+        name.push_str(&self.consume_until_char("attribute name", |c| match c {
+            '=' | '/' | '>' => true,
+            c => c.is_whitespace(),
+        }));
 
-        return attributes;
+        name.to_ascii_lowercase()
     }
 
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
-        let mut nodes = Vec::new();
+    /// An attribute value. One cut off by the end of input is empty, and
+    /// leaves the tag at the end of input for `parse_tag` to drop.
+    fn parse_attr_value(&mut self) -> String {
+        if self.eof() {
+            return String::new();
+        }
+
+        match self.next_char() {
+            quote @ '"' | quote @ '\'' => {
+                self.consume_char();
+                let end = match self.find(if quote == '"' { "\"" } else { "'" }) {
+                    Some(index) => self.pos + index,
+                    None => {
+                        self.pos = self.input.len();
+                        return String::new();
+                    },
+                };
+                let value = entities::decode(&self.input[self.pos..end], true);
+                self.pos = end + 1;
+                value
+            },
+            // `<a b=>` is an empty value, as in browsers.
+            '>' => String::new(),
+            _ => {
+                let value = self.consume_until_char("unquoted attribute value", |c| c == '>' || c.is_whitespace());
+                entities::decode(&value, true)
+            },
+        }
+    }
+
+    /// Read attributes into `attributes` up to `>`, `/>` or the end of input.
+    /// Returns the position of the last attribute read, which is the one
+    /// cut off if the input runs out; the ones before it are complete.
+    fn parse_attributes(&mut self, attributes: &mut dom::AttrMap) -> usize {
+        let mut resume = self.pos;
         loop {
+            if self.waiting() {
+                break;
+            }
+            resume = self.pos;
             self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
+            if self.eof() || self.next_char() == '>' || self.starts_with("/>") {
+                break;
+            }
+            // 多余的 '/' 当作空白
+            if self.next_char() == '/' {
+                self.consume_char();
+                continue;
+            }
+            let (name, value) = self.parse_attr();
+            if self.waiting() {
                 break;
             }
+            // A repeated attribute is dropped; the first one wins.
+            if !attributes.contains_key(&name) {
+                attributes.insert(name, value);
+            }
+        }
+
+        return resume;
+    }
+}
 
-            nodes.push(self.parse_node());
+/// Parse an HTML document, along with the located errors in its markup,
+/// such as a tag cut off by the end of input. Each is recovered from the
+/// way browsers do, so there is always a document.
+///
+/// Missing or misnested tags are not errors: the tree builder repairs them
+/// the way browsers do, so the root is always an `html` element holding a
+/// `head` and a `body`.
+pub fn parse(source: String) -> (dom::Document, Vec<ParseError>) {
+    let mut tokenizer = Tokenizer { input: source, finished: true, ..Tokenizer::new() };
+    let mut builder = TreeBuilder::new();
+    while let Some((token, span)) = tokenizer.next_token() {
+        let done = token == Token::Eof;
+        builder.process_token(token, span);
+        if done {
+            break;
         }
+    }
 
-        return nodes;
+    (builder.finish(), tokenizer.errors)
+}
+
+/// Parse an HTML document from raw bytes, detecting the encoding from a byte
+/// order mark or `<meta charset>` first; see `encoding::decode`.
+pub fn parse_bytes(bytes: &[u8]) -> (dom::Document, Vec<ParseError>) {
+    let (source, _) = encoding::decode(bytes);
+    parse(source)
+}
+
+/// Parses a document fed in chunks, building the tree as tokens complete, so
+/// large documents can be parsed while they are still being produced. The
+/// encoding is sniffed as in `parse_bytes`, from the first chunks only.
+pub struct Parser {
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser { tokenizer: Tokenizer::new(), builder: TreeBuilder::new() }
     }
 
+    /// Feed the next chunk of the document.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for (token, span) in self.tokenizer.feed(bytes) {
+            self.builder.process_token(token, span);
+        }
+    }
+
+    /// Mark the end of the document and return it, with the errors found as
+    /// in `parse`.
+    pub fn finish(mut self) -> (dom::Document, Vec<ParseError>) {
+        for (token, span) in self.tokenizer.finish() {
+            self.builder.process_token(token, span);
+        }
+
+        (self.builder.finish(), self.tokenizer.errors)
+    }
 }
 
-pub fn parse(source: String) -> dom::Node {
-    let mut nodes = Parser { pos: 0, input: source }.parse_nodes();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD: &str = "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<title>Caf&eacute; &amp; more</title>\n\
+        <style>p > a { color: #ff0000; }</style>\n</head>\n<body class='main' data-x=1 hidden>\n";
+
+    const BODY: &str = "<!-- a comment -- with dashes -->\n<?php echo 1 ?>\n\
+        <p>Voilà — “quotes” &lt;tag&gt; &copy; 2024</p>\n<pre>\nline one\n  line two</pre>\n\
+        <script>if (a </b) { x = \"</scr\" + \"ipt>\"; }</script>\n<textarea>&lt;b&gt;</textarea>\n\
+        <ul><li>one<li>two</ul>\n<table><tr><td>a<td>b</table>\n\
+        <p>a < b <b>bold <i>both</b> italic</i></p><br/><img src=\"x.png\" alt='an &quot;x&quot;'>\n";
+
+    /// Long enough that most of it is fed after the encoding is sniffed.
+    fn document() -> String {
+        format!("{}{}</body>\n</html>\n", HEAD, BODY.repeat(8))
+    }
+
+    #[test]
+    fn end_tag_attributes_are_ignored() {
+        let expected = parse("<div>a</div>b".to_string()).0.dump();
+        for source in ["<div>a</div class=x>b", "<div>a</div/>b", "<div>a</div title=\"x>y\" >b"] {
+            assert_eq!(parse(source.to_string()).0.dump(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn tags_cut_off_by_the_end_of_input_are_dropped() {
+        let cases = [
+            ("<b>x</b", "<b>x</b>"),
+            ("<p>a<img src=\"x.png", "<p>a</p>"),
+            ("<p>a<img src='x.png' alt", "<p>a</p>"),
+            ("<p>a<img src=", "<p>a</p>"),
+            ("<p>a<my-element", "<p>a</p>"),
+        ];
+        for (source, complete) in cases {
+            let (document, errors) = parse(source.to_string());
+            assert_eq!(document.dump(), parse(complete.to_string()).0.dump(), "{}", source);
+            let messages: Vec<_> = errors.iter().map(|e| &*e.message).collect();
+            assert_eq!(messages, ["unexpected end of input in tag"], "{}", source);
+            assert_eq!(errors[0].offset, source.rfind('<').unwrap(), "{}", source);
 
-    if nodes.len() == 1 {
-        nodes.swap_remove(0)
-    } else {
-        dom::Node::elem("html".to_string(), HashMap::new(), nodes)
+
+            // Long enough to be fed after the encoding is sniffed.
+            let source = format!("{}{}", "<i>padding</i>".repeat(100), source);
+            let (document, errors) = parse(source.clone());
+            let mut parser = Parser::new();
+            for byte in source.as_bytes() {
+                parser.feed(&[*byte]);
+            }
+            let (streamed, streamed_errors) = parser.finish();
+            assert_eq!((streamed.to_json(), streamed_errors), (document.to_json(), errors), "{}", source);
+        }
+    }
+
+    #[test]
+    fn tag_names_run_to_white_space_slash_or_bracket() {
+        let document = parse("<My-Element a=1>x</MY-ELEMENT><svg:rect/>y".to_string()).0;
+        let labels: Vec<_> = document.descendants(document.root())
+            .map(|id| document[id].label())
+            .filter(|label| label.starts_with("<my") || label.starts_with("<svg"))
+            .collect();
+        assert_eq!(labels, [r#"<my-element a="1">"#, "<svg:rect>"]);
+        let element = document.descendants(document.root())
+            .find(|&id| document[id].label().starts_with("<my-element"))
+            .unwrap();
+        assert_eq!(document.children(element).count(), 1);
+    }
+
+    #[test]
+    fn stray_quotes_are_part_of_attribute_names() {
+        let document = parse("<a href=\"x\"\">a</a><img alt='it''s'>".to_string()).0;
+        let labels: Vec<_> = document.descendants(document.root())
+            .map(|id| document[id].label())
+            .filter(|label| label.starts_with("<a") || label.starts_with("<img"))
+            .collect();
+        assert_eq!(labels, [r#"<a href="x" "="">"#, r#"<img alt="it" 's'="">"#]);
+    }
+
+    #[test]
+    fn chunked_input_parses_like_whole_input() {
+        let document = document();
+        let whole = parse(document.clone()).0.to_json();
+        for size in 1..=8 {
+            let mut parser = Parser::new();
+            for chunk in document.as_bytes().chunks(size) {
+                parser.feed(chunk);
+            }
+            assert_eq!(parser.finish().0.to_json(), whole, "chunks of {} bytes", size);
+        }
     }
-}
\ No newline at end of file
+}
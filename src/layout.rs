@@ -1,24 +1,71 @@
-use super::style::{ StyleNode, Display };
+use std::fmt;
+
+use super::dom::NodeType;
+use super::style::{ StyleNode, Display, WhiteSpace };
 use super::css::{ Value, Unit };
 
-pub use self::BoxType::{AnonymousBlock, InlineNode, BlockNode};
+use serde::ser::{ Serialize, SerializeMap, Serializer };
+
+pub use self::BoxType::{AnonymousBlock, InlineNode, BlockNode, TextNode};
 
 // data struct
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>
 }
 
-#[derive(Debug)]
 pub enum BoxType<'a> {
     BlockNode(&'a StyleNode<'a>),
     InlineNode(&'a StyleNode<'a>),
+    /// A run of text, with its white space already collapsed.
+    TextNode(&'a StyleNode<'a>, String),
     AnonymousBlock,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+// Show which DOM node, and where in the source, a box came from; the
+// style node's own children are already mirrored by the box's children.
+impl<'a> fmt::Debug for BoxType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoxType::BlockNode(style) => f.debug_tuple("BlockNode").field(&style.node.summary()).finish(),
+            BoxType::InlineNode(style) => f.debug_tuple("InlineNode").field(&style.node.summary()).finish(),
+            BoxType::TextNode(style, ref text) => {
+                f.debug_tuple("TextNode").field(&style.node.summary()).field(text).finish()
+            },
+            BoxType::AnonymousBlock => f.write_str("AnonymousBlock"),
+        }
+    }
+}
+
+impl<'a> Serialize for BoxType<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match *self {
+            BoxType::BlockNode(style) => {
+                map.serialize_entry("type", "block")?;
+                map.serialize_entry("id", &style.id)?;
+                map.serialize_entry("node", style.node)?;
+            },
+            BoxType::InlineNode(style) => {
+                map.serialize_entry("type", "inline")?;
+                map.serialize_entry("id", &style.id)?;
+                map.serialize_entry("node", style.node)?;
+            },
+            BoxType::TextNode(style, ref text) => {
+                map.serialize_entry("type", "text")?;
+                map.serialize_entry("id", &style.id)?;
+                map.serialize_entry("node", style.node)?;
+                map.serialize_entry("text", text)?;
+            },
+            BoxType::AnonymousBlock => map.serialize_entry("type", "anonymous")?,
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, serde::Serialize)]
 pub struct Dimensions {
     pub content: Rect,
 
@@ -27,7 +74,7 @@ pub struct Dimensions {
     pub border: EdgeSizes,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, serde::Serialize)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -35,7 +82,7 @@ pub struct Rect {
     pub height: f32,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, serde::Serialize)]
 pub struct EdgeSizes {
     pub left: f32,
     pub right: f32,
@@ -57,7 +104,10 @@ pub fn layout_tree<'a>(node: &'a StyleNode<'a>, mut containing_block: Dimensions
 pub fn build_layout_tree<'a>(style_node: &'a StyleNode) -> LayoutBox<'a> {
     let mut root = LayoutBox::new( match style_node.display() {
         Display::Block => BoxType::BlockNode(style_node),
-        Display::Inline => BoxType::InlineNode(style_node),
+        Display::Inline => match style_node.node.node_type {
+            NodeType::Text(ref text) => BoxType::TextNode(style_node, text.clone()),
+            _ => BoxType::InlineNode(style_node),
+        },
         _ => panic!("The root node's display is invalud or none!"),
     });
 
@@ -69,9 +119,60 @@ pub fn build_layout_tree<'a>(style_node: &'a StyleNode) -> LayoutBox<'a> {
         }
     }
 
+    if let BoxType::BlockNode(_) = root.box_type {
+        root.collapse_white_space();
+    }
+
     return root;
 }
 
+/// Collapse `text` as its `white-space` value says. `after_space` carries
+/// whether the text before it ended in collapsible space, so that runs
+/// collapse across box boundaries; start it at `true` to drop the space at
+/// the start of a line.
+pub fn collapse_white_space(text: &str, white_space: WhiteSpace, after_space: &mut bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        let is_space = match c {
+            ' ' | '\t' | '\n' | '\r' | '\x0c' => true,
+            _ => false,
+        };
+        match white_space {
+            WhiteSpace::Pre | WhiteSpace::PreWrap => {
+                result.push(c);
+                *after_space = false;
+            },
+            WhiteSpace::PreLine if c == '\n' => {
+                // Spaces around a preserved newline go away.
+                if result.ends_with(' ') {
+                    result.pop();
+                }
+                result.push('\n');
+                *after_space = true;
+            },
+            _ if is_space => {
+                if !*after_space {
+                    result.push(' ');
+                    *after_space = true;
+                }
+            },
+            _ => {
+                result.push(c);
+                *after_space = false;
+            },
+        }
+    }
+
+    return result;
+}
+
+fn collapses_spaces(white_space: WhiteSpace) -> bool {
+    match white_space {
+        WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine => true,
+        WhiteSpace::Pre | WhiteSpace::PreWrap => false,
+    }
+}
+
 impl Rect {
     fn expanded_by(&self, edge: EdgeSizes) -> Rect {
         Rect {
@@ -97,7 +198,53 @@ impl Dimensions {
     }
 }
 
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x={} y={} width={} height={}", self.x, self.y, self.width, self.height)
+    }
+}
+
 impl<'a> LayoutBox<'a> {
+    /// The layout tree indented two spaces per level: each box's type, node
+    /// and source location, then its content, padding, border and margin
+    /// boxes.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(0, &mut out);
+
+        out
+    }
+
+    /// The layout tree as pretty-printed JSON, with all box dimensions. Boxes
+    /// carry the `"id"` of their node, as in `Document::to_json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a layout tree always serializes")
+    }
+
+    fn dump_into(&self, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                out.push_str(&format!("{}BlockNode {}{}\n", indent, style.node.label(), style.node.location()))
+            },
+            BoxType::InlineNode(style) => {
+                out.push_str(&format!("{}InlineNode {}{}\n", indent, style.node.label(), style.node.location()))
+            },
+            BoxType::TextNode(style, ref text) => {
+                out.push_str(&format!("{}TextNode {:?}{}\n", indent, text, style.node.location()))
+            },
+            BoxType::AnonymousBlock => out.push_str(&format!("{}AnonymousBlock\n", indent)),
+        }
+        let d = self.dimensions;
+        out.push_str(&format!("{}  content {}\n", indent, d.content));
+        out.push_str(&format!("{}  padding {}\n", indent, d.padding_box()));
+        out.push_str(&format!("{}  border {}\n", indent, d.border_box()));
+        out.push_str(&format!("{}  margin {}\n", indent, d.margin_box()));
+        for child in &self.children {
+            child.dump_into(depth + 1, out);
+        }
+    }
+
     pub fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
             box_type,
@@ -110,13 +257,14 @@ impl<'a> LayoutBox<'a> {
         match self.box_type {
             BoxType::BlockNode(node) => node,
             BoxType::InlineNode(node) => node,
+            BoxType::TextNode(node, _) => node,
             BoxType::AnonymousBlock => panic!("There is not style node within AnonymousBlock"),
         }
     }
 
     pub fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
+            BoxType::InlineNode(_) | BoxType::TextNode(..) | BoxType::AnonymousBlock => self,
             BoxType::BlockNode(_) => {
                 match self.children.last() {
                     Some(&LayoutBox { box_type: BoxType::AnonymousBlock, .. }) => {},
@@ -128,10 +276,62 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
+    /// Apply `white-space` to the text of each anonymous block, the inline
+    /// formatting contexts of this block, and drop the ones left empty.
+    fn collapse_white_space(&mut self) {
+        for child in &mut self.children {
+            if let BoxType::AnonymousBlock = child.box_type {
+                let mut after_space = true;
+                child.collapse_inline_white_space(&mut after_space);
+
+                // Collapsible space at the end of the line goes too.
+                if let Some(&mut LayoutBox { box_type: BoxType::TextNode(style, ref mut text), .. }) = child.last_text_mut() {
+                    if collapses_spaces(style.white_space()) && text.ends_with(' ') {
+                        text.pop();
+                    }
+                }
+                child.remove_empty_text();
+            }
+        }
+
+        self.children.retain(|child| match child.box_type {
+            BoxType::AnonymousBlock => !child.children.is_empty(),
+            _ => true,
+        });
+    }
+
+    fn collapse_inline_white_space(&mut self, after_space: &mut bool) {
+        if let BoxType::TextNode(style, ref mut text) = self.box_type {
+            *text = collapse_white_space(text, style.white_space(), after_space);
+        }
+        for child in &mut self.children {
+            child.collapse_inline_white_space(after_space);
+        }
+    }
+
+    /// The last text box with any text left after collapsing.
+    fn last_text_mut(&mut self) -> Option<&mut LayoutBox<'a>> {
+        if let BoxType::TextNode(_, ref text) = self.box_type {
+            return if text.is_empty() { None } else { Some(self) };
+        }
+
+        self.children.iter_mut().rev().find_map(|child| child.last_text_mut())
+    }
+
+    fn remove_empty_text(&mut self) {
+        self.children.retain(|child| match child.box_type {
+            BoxType::TextNode(_, ref text) => !text.is_empty(),
+            _ => true,
+        });
+        for child in &mut self.children {
+            child.remove_empty_text();
+        }
+    }
+
     pub fn layout(&mut self, containing_block: Dimensions) {
         match self.box_type {
             BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => {},
+            BoxType::InlineNode(_) | BoxType::TextNode(..) | BoxType::AnonymousBlock => {},
         }
     }
 
@@ -170,11 +370,11 @@ impl<'a> LayoutBox<'a> {
 
         // 调整
         if width != auto && total > containing_block.content.width {
-            if(margin_left == auto) {
+            if margin_left == auto {
                 margin_left = Value::Length(0.0, Unit::Px);
             }
 
-            if(margin_right == auto) {
+            if margin_right == auto {
                 margin_right = Value::Length(0.0, Unit::Px);
             }
         }
@@ -208,7 +408,7 @@ impl<'a> LayoutBox<'a> {
             }
         }
 
-        let mut d = &mut self.dimensions;
+        let d = &mut self.dimensions;
         d.content.width = width.to_px();
 
         d.padding.left = padding_left.to_px();
@@ -261,4 +461,103 @@ impl<'a> LayoutBox<'a> {
 
 fn sum<I>(iter: I) -> f32 where I: Iterator<Item=f32> {
     iter.fold(0., |a, b| a + b)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ css, html, style };
+
+    #[test]
+    fn dump_shows_where_boxes_came_from() {
+        let document = html::parse("<p>x\n<b>y</b></p>".to_string()).0;
+        let (stylesheet, _) = css::parse("html, body, p { display: block }".to_string());
+        let style_root = style::style_tree(&document, &stylesheet);
+        let dump = layout_tree(&style_root, Default::default()).dump();
+        assert!(dump.contains("\n    BlockNode <p> at 1:1-2:13\n"), "{}", dump);
+        assert!(dump.contains("TextNode \"x \" at 1:4-2:1\n"), "{}", dump);
+        assert!(dump.contains("InlineNode <b> at 2:1-2:9\n"), "{}", dump);
+    }
+    #[test]
+    fn json_boxes_carry_document_ids() {
+        let document = html::parse("<p>x</p>".to_string()).0;
+        let (stylesheet, _) = css::parse("html, body, p { display: block }".to_string());
+        let style_root = style::style_tree(&document, &stylesheet);
+        let json = layout_tree(&style_root, Default::default()).to_json();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        let mut boxes = vec![&json];
+        let mut ids = Vec::new();
+        while let Some(layout_box) = boxes.pop() {
+            if let Some(id) = layout_box["box_type"].get("id") {
+                ids.push(id.as_u64().unwrap());
+            }
+            boxes.extend(layout_box["children"].as_array().unwrap());
+        }
+        ids.sort_unstable();
+        let expected: Vec<_> = document.preorder(document.root())
+            .map(|id| serde_json::to_value(id).unwrap().as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    /// The text of each text box laid out for `source`, in document order.
+    fn texts(source: &str) -> Vec<String> {
+        fn collect(layout_box: &LayoutBox, out: &mut Vec<String>) {
+            if let BoxType::TextNode(_, ref text) = layout_box.box_type {
+                out.push(text.clone());
+            }
+            for child in &layout_box.children {
+                collect(child, out);
+            }
+        }
+
+        let document = html::parse(source.to_string()).0;
+        let (stylesheet, _) = css::parse("html, body, p, div { display: block }".to_string());
+        let style_root = style::style_tree(&document, &stylesheet);
+        let mut out = Vec::new();
+        collect(&layout_tree(&style_root, Default::default()), &mut out);
+
+        out
+    }
+
+    #[test]
+    fn white_space_modes() {
+        let text = "  a \t b \n\n  c  ";
+        let cases = [
+            (WhiteSpace::Normal, "a b c ", true),
+            (WhiteSpace::Nowrap, "a b c ", true),
+            (WhiteSpace::Pre, text, false),
+            (WhiteSpace::PreWrap, text, false),
+            (WhiteSpace::PreLine, "a b\n\nc ", true),
+        ];
+        for (white_space, expected, ends_in_space) in cases {
+            let mut after_space = true;
+            assert_eq!(collapse_white_space(text, white_space, &mut after_space), expected, "{:?}", white_space);
+            assert_eq!(after_space, ends_in_space, "{:?}", white_space);
+        }
+
+        // Without space before it, leading space is kept as one.
+        let mut after_space = false;
+        assert_eq!(collapse_white_space("\n a", WhiteSpace::Normal, &mut after_space), " a");
+    }
+
+    #[test]
+    fn white_space_collapses_across_boxes() {
+        assert_eq!(texts("<p>  a <b> b </b> c  </p>"), ["a ", "b ", "c"]);
+        assert_eq!(texts("<p>a<i> </i> <b> b</b></p>"), ["a", " ", "b"]);
+        // Preserved space does not swallow what follows.
+        assert_eq!(texts("<p>a <span style=\"white-space: pre\">  b  </span> c</p>"), ["a ", "  b  ", " c"]);
+        assert_eq!(texts("<pre>\n  a  \n</pre>"), ["  a  \n"]);
+    }
+
+    #[test]
+    fn white_space_at_line_ends() {
+        // Collapsible space at the end of each block's line is dropped,
+        // along with text boxes left empty.
+        assert_eq!(texts("<div>a <b>b </b> </div><div> </div><div>c</div>"), ["a ", "b", "c"]);
+        assert_eq!(texts("<div>a<i> </i></div>"), ["a"]);
+        assert_eq!(texts("<p style=\"white-space: pre-wrap\">a </p>"), ["a "]);
+        assert_eq!(texts("<p style=\"white-space: pre-line\"> a \n b </p>"), ["a\nb"]);
+    }
+}
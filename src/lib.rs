@@ -0,0 +1,15 @@
+// The early modules lean on explicit `return` and exhaustive `match` over
+// character ranges; keep that style rather than churn it for clippy.
+#![allow(clippy::needless_return, clippy::match_like_matches_macro, clippy::enum_variant_names)]
+
+pub mod dom;
+pub mod encoding;
+pub mod html;
+pub mod serialize;
+mod entities;
+mod tree_builder;
+pub mod css;
+pub mod style;
+pub mod query;
+pub mod layout;
+pub mod painting;
@@ -1,41 +1,54 @@
 extern crate image;
 
-use std::collections::HashMap;
-
-mod dom;
-mod html;
-mod css;
-mod style;
-mod layout;
-mod painting;
-
-use std::fs::File;
+use std::env;
+use std::fs;
 use std::path::Path;
 
+use skylight_2::{ dom, html, style, layout, painting };
+
 fn main() {
     println!("Hello, world!");
 
-    let node = dom::Node::text("First Node".to_string());
+    let node = dom::Tree::text("First Node".to_string());
     println!("{:?}", node);
 
-    let node = dom::Node::elem("Element Node".to_string(), HashMap::new(), Vec::new() );
+    let node = dom::Tree::elem("Element Node".to_string(), dom::AttrMap::new(), Vec::new() );
     println!("{:?}", node);
 
     // Test Html Parser
     //let source = "<div><div a=\"b\">123</div><div class=\"myclass\">456</div></div>";
     //let source = "<div class=\"a\"><div class=\"b\"><div class=\"c\"><div class=\"d\"><div class=\"e\"><div class=\"f\"><div class=\"g\"></div></div></div></div></div></div></div>";
-    let source = "<div class=\"a\"><div class=\"b\"><div class=\"c\"></div></div></div>";
-    let node = html::parse(source.to_string());
-    println!("html 格式化后: {:?}", node);
+    // 可以传入 html 文件路径, 样式取自文档中的 <style> 和 <link>
+    let document_path = env::args().nth(1);
+    let source = match document_path {
+        Some(ref path) => match fs::read(path) {
+            Ok(source) => source,
+            Err(err) => {
+                println!("无法读取 {}: {}", path, err);
+                return;
+            }
+        },
+        None => DEMO_SOURCE.as_bytes().to_vec(),
+    };
+    let (document, errors) = html::parse_bytes(&source);
+    for err in errors {
+        println!("html 解析错误: {}", err);
+    }
+    println!("html 格式化后:\n{}", document.dump());
 
     // Test CSS Parser
-    let source = "* { display: block; padding: 50px; } .a { background: #4cb4e7; } .b { background: #ffc09f; } .c { background: #ffee93; } .d { background: #008000; } .e { background: #0000ff; } .f { background: #4b0082; } .g { background: #800080; }";
-    let stylesheet = css::parse(source.to_string());
+    let (stylesheet, errors, warnings) = style::document_stylesheet(&document, document_path.as_ref().map(Path::new));
+    for err in errors {
+        println!("样式表加载失败: {}", err);
+    }
+    for warning in warnings {
+        println!("样式表解析警告: {}", warning);
+    }
     println!("CSS 格式化后: {:?}", stylesheet);
 
     // Test Style Module
-    let style_tree_result = style::style_tree(&node, &stylesheet);
-    println!("Style Tree 格式化后: {:?}", style_tree_result);
+    let style_tree_result = style::style_tree(&document, &stylesheet);
+    println!("Style Tree 格式化后:\n{}", style_tree_result.dump());
 
     // Test Layout Module
     let initial_containing_block = layout::Dimensions {
@@ -46,21 +59,34 @@ fn main() {
     };
 
     let layout_tree = layout::layout_tree(&style_tree_result, initial_containing_block);
-    println!("Layout Tree 格式化后: {:?}", layout_tree);
+    println!("Layout Tree 格式化后:\n{}", layout_tree.dump());
 
     // Test Paiting Module
     let canvas = painting::paint(&layout_tree, initial_containing_block.content);
     let path = Path::new("output.png");
-    let file = File::create(&path).unwrap();
 
     // Save an image:
     let (w, h) = (canvas.width as u32, canvas.height as u32);
     let buffer: Vec<image::Rgba<u8>> = unsafe { std::mem::transmute(canvas.pixels) };
     let img = image::ImageBuffer::from_fn(w, h, |x: u32, y: u32| buffer[(y * w + x) as usize]);
 
-    let result = image::ImageRgba8(img).save(&path);
+    let result = image::ImageRgba8(img).save(path);
     match result {
         Ok(_) => println!("Saved output success"),
         Err(_) => println!("Error saving output failed")
     }
 }
+
+const DEMO_SOURCE: &str = "<!DOCTYPE html>
+<html>
+<head>
+<style>
+* { display: block; padding: 50px; }
+html, body { padding: 0px; }
+head { display: none; }
+.a { background: #4cb4e7; } .b { background: #ffc09f; } .c { background: #ffee93; } .d { background: #008000; }
+.e { background: #0000ff; } .f { background: #4b0082; } .g { background: #800080; }
+</style>
+</head>
+<body><div class=\"a\"><div class=\"b\"><div class=\"c\"></div></div></div></body>
+</html>";
@@ -1,4 +1,4 @@
-use super::layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox, Rect};
+use super::layout::{AnonymousBlock, BlockNode, InlineNode, TextNode, LayoutBox, Rect};
 use super::css::{Value, Color};
 
 pub struct Canvas {
@@ -38,8 +38,9 @@ fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color|
-        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box())));
+    if let Some(color) = get_color(layout_box, "background") {
+        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box()));
+    }
 }
 
 fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
@@ -87,7 +88,7 @@ fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
 /// Return the specified color for CSS property `name`, or None if no color was specified.
 fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
     match layout_box.box_type {
-        BlockNode(style) | InlineNode(style) => match style.value(name) {
+        BlockNode(style) | InlineNode(style) | TextNode(style, _) => match style.value(name) {
             Some(Value::ColorValue(color)) => Some(color),
             _ => None
         },
@@ -101,8 +102,8 @@ impl Canvas {
         let white = Color { r: 255, g: 255, b: 255, a: 255 };
         Canvas {
             pixels: vec![white; width * height],
-            width: width,
-            height: height,
+            width,
+            height,
         }
     }
 
@@ -125,12 +126,3 @@ impl Canvas {
         }
     }
 }
-
-trait Clamp {
-    fn clamp(self, lower: Self, upper: Self) -> Self;
-}
-impl Clamp for f32 {
-    fn clamp(self, lower: f32, upper: f32) -> f32 {
-        self.max(lower).min(upper)
-    }
-}
\ No newline at end of file
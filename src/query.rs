@@ -0,0 +1,28 @@
+// Finding elements by selector, as `querySelector` does in browsers.
+
+use super::css::{ self, Selector, SelectorError };
+use super::dom::{ Document, NodeId, NodeType };
+use super::style;
+
+/// The first element below `scope`, in document order, that matches any of
+/// the comma-separated `selectors`. Like `Element.querySelector`, `scope`
+/// itself is not a candidate.
+pub fn query_selector(document: &Document, scope: NodeId, selectors: &str) -> Result<Option<NodeId>, SelectorError> {
+    let selectors = css::parse_selector_list(selectors.to_string())?;
+
+    Ok(document.descendants(scope).find(|&node| matches_any(document, node, &selectors)))
+}
+
+/// Every element below `scope` that matches any of `selectors`, in document order.
+pub fn query_selector_all(document: &Document, scope: NodeId, selectors: &str) -> Result<Vec<NodeId>, SelectorError> {
+    let selectors = css::parse_selector_list(selectors.to_string())?;
+
+    Ok(document.descendants(scope).filter(|&node| matches_any(document, node, &selectors)).collect())
+}
+
+fn matches_any(document: &Document, node: NodeId, selectors: &[Selector]) -> bool {
+    match document[node].node_type {
+        NodeType::Element(ref elem) => selectors.iter().any(|selector| style::matches(elem, selector)),
+        _ => false,
+    }
+}
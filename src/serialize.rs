@@ -0,0 +1,214 @@
+// Writing the DOM back out as HTML.
+
+use super::dom::{ Document, Doctype, ElementData, NodeId, NodeType };
+use super::html;
+
+/// Elements whose leading newline the parser drops, so one more is written
+/// to keep a newline that was really in the content.
+const LEADING_NEWLINE_ELEMENTS: &[&str] = &["listing", "pre", "textarea"];
+
+/// Serialize a node of `document` and its descendants.
+///
+/// Plain output reads back through `html::parse` as the same tree. Pretty
+/// output puts nested elements on their own indented lines, adding and
+/// trimming white space between them; text inside preformatted and raw text
+/// elements is left as it is.
+pub fn serialize(document: &Document, node: NodeId, pretty: bool) -> String {
+    let mut out = String::new();
+    if pretty {
+        write_pretty(document, node, 0, &mut out);
+    } else {
+        write_node(document, node, false, &mut out);
+    }
+
+    out
+}
+
+/// Serialize a whole document, DOCTYPE first.
+pub fn serialize_document(document: &Document, pretty: bool) -> String {
+    let mut out = String::new();
+    if let Some(ref doctype) = document.doctype {
+        write_doctype(doctype, &mut out);
+        if pretty {
+            out.push('\n');
+        }
+    }
+    out.push_str(&serialize(document, document.root(), pretty));
+
+    out
+}
+
+fn write_node(document: &Document, node: NodeId, raw_text: bool, out: &mut String) {
+    match document[node].node_type {
+        NodeType::Text(ref text) if raw_text => out.push_str(text),
+        NodeType::Text(ref text) => escape_text(text, out),
+        NodeType::Comment(ref data) => write_comment(data, out),
+        NodeType::Element(ref elem) => {
+            write_start_tag(elem, out);
+            if html::is_void_element(&elem.tag_name) {
+                return;
+            }
+            if starts_with_newline(document, node, elem) {
+                out.push('\n');
+            }
+            let raw_text = html::is_raw_text_element(&elem.tag_name);
+            for child in document.children(node) {
+                write_node(document, child, raw_text, out);
+            }
+            write_end_tag(elem, out);
+        },
+    }
+}
+
+fn write_pretty(document: &Document, node: NodeId, depth: usize, out: &mut String) {
+    match document[node].node_type {
+        NodeType::Text(ref text) => {
+            let text = text.trim();
+            if text.is_empty() {
+                return;
+            }
+            indent(depth, out);
+            escape_text(text, out);
+        },
+        NodeType::Comment(ref data) => {
+            indent(depth, out);
+            write_comment(data, out);
+        },
+        NodeType::Element(ref elem) => {
+            indent(depth, out);
+            // Elements holding only text stay on one line, as do those
+            // whose white space is part of the content.
+            let inline = LEADING_NEWLINE_ELEMENTS.contains(&&*elem.tag_name)
+                || html::is_raw_text_element(&elem.tag_name)
+                || document.children(node).all(|child| match document[child].node_type {
+                    NodeType::Text(_) => true,
+                    _ => false,
+                });
+            if inline || html::is_void_element(&elem.tag_name) {
+                write_node(document, node, false, out);
+            } else {
+                write_start_tag(elem, out);
+                out.push('\n');
+                for child in document.children(node) {
+                    write_pretty(document, child, depth + 1, out);
+                }
+                indent(depth, out);
+                write_end_tag(elem, out);
+            }
+        },
+    }
+    out.push('\n');
+}
+
+fn indent(depth: usize, out: &mut String) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn starts_with_newline(document: &Document, node: NodeId, elem: &ElementData) -> bool {
+    LEADING_NEWLINE_ELEMENTS.contains(&&*elem.tag_name)
+        && document.first_child(node).is_some_and(|child| match document[child].node_type {
+            // `\r\n` and a lone `\r` are newlines too.
+            NodeType::Text(ref text) => text.starts_with(['\n', '\r']),
+            _ => false,
+        })
+}
+
+fn write_start_tag(elem: &ElementData, out: &mut String) {
+    out.push('<');
+    out.push_str(&elem.tag_name);
+    for (name, value) in &elem.attributes {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        escape_attribute(value, out);
+        out.push('"');
+    }
+    out.push('>');
+}
+
+fn write_end_tag(elem: &ElementData, out: &mut String) {
+    out.push_str("</");
+    out.push_str(&elem.tag_name);
+    out.push('>');
+}
+
+fn write_comment(data: &str, out: &mut String) {
+    out.push_str("<!--");
+    out.push_str(data);
+    out.push_str("-->");
+}
+
+fn write_doctype(doctype: &Doctype, out: &mut String) {
+    out.push_str("<!DOCTYPE ");
+    out.push_str(&doctype.name);
+    match (&doctype.public_id, &doctype.system_id) {
+        (Some(public_id), system_id) => {
+            out.push_str(" PUBLIC ");
+            write_quoted(public_id, out);
+            if let Some(system_id) = system_id {
+                out.push(' ');
+                write_quoted(system_id, out);
+            }
+        },
+        (None, Some(system_id)) => {
+            out.push_str(" SYSTEM ");
+            write_quoted(system_id, out);
+        },
+        (None, None) => {},
+    }
+    out.push('>');
+}
+
+/// DOCTYPE identifiers have no escapes, so pick the quote they don't contain.
+fn write_quoted(id: &str, out: &mut String) {
+    let quote = if id.contains('"') { '\'' } else { '"' };
+    out.push(quote);
+    out.push_str(id);
+    out.push(quote);
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_output_parses_back_to_the_same_tree() {
+        let sources = [
+            "<!DOCTYPE html><p class=\"a &quot;b&quot;\" title='x&amp;y'>1 &lt; 2 &amp;&nbsp;3</p><!-- c -->",
+            "<ul><li>one<li>two</ul><br><img src=x.png alt=\"\"><table><tr><td>a</table>",
+            "<script>if (a < b && c) {}</script><style>p > a {}</style><textarea>&lt;b&gt;</textarea>",
+            "<pre>\n\nx</pre><pre>\n\r\nx</pre><pre>\n\rx</pre><listing>\n\ny</listing>",
+            "<textarea>\n\r\nz</textarea><textarea>\n\nz</textarea>",
+        ];
+        for source in sources {
+            let document = html::parse(source.to_string()).0;
+            let output = serialize_document(&document, false);
+            assert_eq!(html::parse(output.clone()).0.dump(), document.dump(), "{:?}", output);
+        }
+    }
+}
@@ -1,28 +1,113 @@
-use std::collections::HashMap;
-use super::dom::{ ElementData, Node, NodeType };
-use super::css::{ Selector, SimpleSelector, Rule, Specificity, Stylesheet, Value };
+use std::collections::{ BTreeMap, HashMap };
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use super::dom::{ Document, ElementData, Node, NodeId, NodeType, SourcePosition };
+use super::css::{ self, Selector, SimpleSelector, Rule, Specificity, Stylesheet, Value };
+
+use serde::ser::{ Serialize, SerializeMap, Serializer };
 
 pub type PropertyMap = HashMap<String, Value>;
 
+/// Properties that children take from their parent unless they set them.
+const INHERITED_PROPERTIES: &[&str] = &["white-space"];
+
+/// Elements that keep their white space unless a stylesheet says otherwise.
+const PREFORMATTED_ELEMENTS: &[&str] = &["listing", "plaintext", "pre", "textarea", "xmp"];
+
 pub enum Display {
     Inline,
     Block,
     None,
 }
 
-#[derive(Debug)]
+/// How the white space in text is collapsed and wrapped.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
 pub struct StyleNode<'a> {
+    /// Where `node` is in its document, the `"id"` it has in `Document::to_json`.
+    pub id: NodeId,
     pub node: &'a Node,
     pub specified_values: PropertyMap,
     pub children: Vec<StyleNode<'a>>,
 }
 
+// The DOM node is summarised with its source location rather than dumped
+// along with its whole subtree.
+impl<'a> fmt::Debug for StyleNode<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StyleNode")
+            .field("id", &self.id)
+            .field("node", &self.node.summary())
+            .field("specified_values", &self.specified_values)
+            .field("children", &self.children)
+            .finish()
+    }
+}
+
+// The node without its children, and the values sorted by name.
+impl<'a> Serialize for StyleNode<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(4))?;
+        map.serialize_entry("id", &self.id)?;
+        map.serialize_entry("node", self.node)?;
+        let values: BTreeMap<_, _> = self.specified_values.iter().collect();
+        map.serialize_entry("specified_values", &values)?;
+        map.serialize_entry("children", &self.children)?;
+        map.end()
+    }
+}
+
 impl<'a> StyleNode<'a> {
     pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+        self.specified_values.get(name).cloned()
+    }
+
+    /// The style tree indented two spaces per level, each node followed by
+    /// where it is in the source and its specified values sorted by name.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(0, &mut out);
+
+        out
+    }
+
+    /// The style tree as pretty-printed JSON, values keeping their CSS types.
+    /// Nodes carry the same `"id"` as in `Document::to_json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a style tree always serializes")
+    }
+
+    fn dump_into(&self, depth: usize, out: &mut String) {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&self.node.label());
+        out.push_str(&self.node.location());
+        if !self.specified_values.is_empty() {
+            let mut values: Vec<_> = self.specified_values.iter().collect();
+            values.sort_by(|a, b| a.0.cmp(b.0));
+            let values: Vec<String> = values.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+            out.push_str(&format!(" {{ {} }}", values.join("; ")));
+        }
+        out.push('\n');
+        for child in &self.children {
+            child.dump_into(depth + 1, out);
+        }
     }
 
     pub fn display(&self) -> Display {
+        if let NodeType::Comment(_) = self.node.node_type {
+            return Display::None;
+        }
+
         match self.value("display") {
             Some(Value::Keyword(s)) => match &*s {
                 "block" => Display::Block,
@@ -33,28 +118,195 @@ impl<'a> StyleNode<'a> {
         }
     }
 
+    pub fn white_space(&self) -> WhiteSpace {
+        match self.value("white-space") {
+            Some(Value::Keyword(s)) => match &*s {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                _ => WhiteSpace::Normal,
+            },
+            _ => WhiteSpace::Normal
+        }
+    }
+
     pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
         self.value(name).unwrap_or_else(|| self.value(fallback_name)
             .unwrap_or_else(|| default.clone()))
     }
 }
 
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyleNode<'a> {
+pub fn style_tree<'a>(document: &'a Document, stylesheet: &'a Stylesheet) -> StyleNode<'a> {
+    style_subtree(document, document.root(), stylesheet, &HashMap::new())
+}
+
+fn style_subtree<'a>(document: &'a Document, id: NodeId, stylesheet: &'a Stylesheet, parent_values: &PropertyMap) -> StyleNode<'a> {
+    let root = &document[id];
+    let mut values = match root.node_type {
+        NodeType::Element(ref elem) => specified_values(elem, stylesheet),
+        NodeType::Text(_) | NodeType::Comment(_) => HashMap::new(),
+    };
+    for name in INHERITED_PROPERTIES {
+        if let Some(value) = parent_values.get(*name) {
+            values.entry(name.to_string()).or_insert_with(|| value.clone());
+        }
+    }
+
     StyleNode {
+        id,
         node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, &stylesheet),
-            NodeType::Text(_) => HashMap::new(),
-        },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect()
+        children: document.children(id)
+            .filter(|&child| match document[child].node_type {
+                NodeType::Comment(_) => false,
+                _ => true,
+            })
+            .map(|child| style_subtree(document, child, stylesheet, &values)).collect(),
+        specified_values: values,
+    }
+}
+
+/// A `<link rel="stylesheet">` whose file could not be read.
+#[derive(Debug)]
+pub struct StylesheetError {
+    pub href: String,
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for StylesheetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot load stylesheet {:?} from {}: {}", self.href, self.path.display(), self.error)
+    }
+}
+
+impl Error for StylesheetError {}
+
+/// Something skipped while parsing a stylesheet: in the linked file `href`,
+/// or in a `<style>` element, located within the document.
+#[derive(Debug)]
+pub struct StylesheetWarning {
+    pub href: Option<String>,
+    pub warning: css::ParseWarning,
+}
+
+impl fmt::Display for StylesheetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.href {
+            Some(ref href) => write!(f, "in stylesheet {:?}: {}", href, self.warning),
+            None => write!(f, "in <style>: {}", self.warning),
+        }
+    }
+}
+
+/// Collect the rules of every `<style>` element and linked stylesheet into
+/// one stylesheet, in document order, so later sheets win ties in the
+/// cascade. Links are resolved relative to `document_path`, or to the
+/// working directory when the document has no file.
+///
+/// Stylesheets that fail to load are skipped and reported, as in browsers,
+/// and so are the parts of a stylesheet that fail to parse.
+pub fn document_stylesheet(document: &Document, document_path: Option<&Path>) -> (Stylesheet, Vec<StylesheetError>, Vec<StylesheetWarning>) {
+    let base_dir = document_path.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    collect_style_rules(document, document.root(), base_dir, &mut rules, &mut errors, &mut warnings);
+
+    (Stylesheet { rules }, errors, warnings)
+}
+
+fn collect_style_rules(document: &Document, node: NodeId, base_dir: &Path, rules: &mut Vec<Rule>,
+                       errors: &mut Vec<StylesheetError>, warnings: &mut Vec<StylesheetWarning>) {
+    if let NodeType::Element(ref elem) = document[node].node_type {
+        if elem.tag_name == "link" && is_stylesheet_link(elem) {
+            let href = elem.attributes.get("href").map_or("", |href| href.trim());
+            if !href.is_empty() {
+                match load_stylesheet(base_dir, href) {
+                    Ok((stylesheet, sheet_warnings)) => {
+                        rules.extend(stylesheet.rules);
+                        warnings.extend(sheet_warnings.into_iter()
+                            .map(|warning| StylesheetWarning { href: Some(href.to_string()), warning }));
+                    },
+                    Err(err) => errors.push(err),
+                }
+            }
+            return;
+        }
+        if elem.tag_name == "style" && is_css(elem) {
+            let source: String = document.children(node)
+                .filter_map(|child| match document[child].node_type {
+                    NodeType::Text(ref text) => Some(&**text),
+                    _ => None,
+                })
+                .collect();
+            let start = document.first_child(node).and_then(|child| document[child].source).map(|span| span.start);
+            let (stylesheet, sheet_warnings) = css::parse(source);
+            rules.extend(stylesheet.rules);
+            warnings.extend(sheet_warnings.into_iter()
+                .map(|warning| StylesheetWarning { href: None, warning: in_document(warning, start) }));
+            return;
+        }
+    }
+
+    for child in document.children(node) {
+        collect_style_rules(document, child, base_dir, rules, errors, warnings);
+    }
+}
+
+/// Move a warning from a `<style>` element's text to the document, given
+/// where that text starts.
+fn in_document(mut warning: css::ParseWarning, start: Option<SourcePosition>) -> css::ParseWarning {
+    if let Some(start) = start {
+        if warning.line == 1 {
+            warning.column += start.column - 1;
+        }
+        warning.line += start.line - 1;
+        warning.offset += start.offset;
+    }
+
+    warning
+}
+
+fn is_stylesheet_link(elem: &ElementData) -> bool {
+    let rel = match elem.attributes.get("rel") {
+        Some(rel) => rel,
+        None => return false,
+    };
+    let mut keywords = rel.split_ascii_whitespace();
+    // Alternate stylesheets are only applied when the user picks them.
+    is_css(elem)
+        && keywords.clone().any(|keyword| keyword.eq_ignore_ascii_case("stylesheet"))
+        && !keywords.any(|keyword| keyword.eq_ignore_ascii_case("alternate"))
+}
+
+fn load_stylesheet(base_dir: &Path, href: &str) -> Result<(Stylesheet, Vec<css::ParseWarning>), StylesheetError> {
+    // Only the path part of the reference names a file.
+    let file = href.split(['?', '#']).next().unwrap_or("");
+    let file = file.strip_prefix("file://").unwrap_or(file);
+    let path = base_dir.join(file);
+
+    match fs::read_to_string(&path) {
+        Ok(source) => Ok(css::parse(source)),
+        Err(error) => Err(StylesheetError { href: href.to_string(), path, error }),
+    }
+}
+
+fn is_css(elem: &ElementData) -> bool {
+    match elem.attributes.get("type") {
+        Some(mime) => mime.is_empty() || mime.eq_ignore_ascii_case("text/css"),
+        None => true,
     }
 }
 
 fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
     let mut values = HashMap::new();
+    if PREFORMATTED_ELEMENTS.contains(&&*elem.tag_name) {
+        values.insert("white-space".to_string(), Value::Keyword("pre".to_string()));
+    }
     let mut rules = match_rules(elem, stylesheet);
 
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b) );
+    rules.sort_by_key(|&(specificity, _)| specificity);
 
     for (_, rule) in rules {
         for declaration in &rule.declarations {
@@ -62,6 +314,15 @@ fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap
         }
     }
 
+    // The style attribute outranks every selector.
+    if let Some(style) = elem.attributes.get("style") {
+        // A style attribute has nowhere to report the declarations it drops.
+        let (declarations, _) = css::parse_declarations(style.clone());
+        for declaration in declarations {
+            values.insert(declaration.name, declaration.value);
+        }
+    }
+
     return values;
 }
 
@@ -73,11 +334,11 @@ fn match_rules<'a>(elem: &'a ElementData, stylesheet: &'a Stylesheet) -> Vec<Mat
 }
 
 fn match_rule<'a>(elem: &'a ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-    rule.selectors.iter().find(|selector| matches(elem, *selector))
+    rule.selectors.iter().find(|selector| matches(elem, selector))
         .map(|selector| (selector.specificity(), rule))
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+pub fn matches(elem: &ElementData, selector: &Selector) -> bool {
     match *selector {
         Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
     }
@@ -85,7 +346,8 @@ fn matches(elem: &ElementData, selector: &Selector) -> bool {
 
 fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
 
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
+    // HTML element names match type selectors regardless of case.
+    if selector.tag_name.iter().any(|name| !name.eq_ignore_ascii_case(&elem.tag_name)) {
         return false;
     }
 
@@ -99,4 +361,70 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
     }
 
     return true;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    #[test]
+    fn style_attribute_ending_in_non_ascii() {
+        let document = html::parse("<p style=\"font-family: café\">x</p>".to_string()).0;
+        let p = document.descendants(document.root())
+            .find(|&id| document[id].label().starts_with("<p"))
+            .unwrap();
+        let stylesheet = Stylesheet { rules: Vec::new() };
+        let tree = style_subtree(&document, p, &stylesheet, &HashMap::new());
+        assert_eq!(tree.value("font-family"), Some(Value::Keyword("café".to_string())));
+    }
+    #[test]
+    fn dump_shows_where_nodes_came_from() {
+        let document = html::parse("<p>x\n<b>y</b></p>".to_string()).0;
+        let (stylesheet, _) = css::parse("p { display: block }".to_string());
+        let dump = style_tree(&document, &stylesheet).dump();
+        assert!(dump.contains("\n    <p> at 1:1-2:13 { display: block }\n"), "{}", dump);
+        assert!(dump.contains("\n      \"x\\n\" at 1:4-2:1\n"), "{}", dump);
+        assert!(dump.contains("\n      <b> at 2:1-2:9\n"), "{}", dump);
+    }
+    /// Each `"id"` in a JSON tree paired with the `"node"` beside it, or in
+    /// a document, with the node's own fields.
+    fn ids_and_nodes(json: &serde_json::Value, out: &mut Vec<(serde_json::Value, serde_json::Value)>) {
+        match json {
+            serde_json::Value::Object(map) => {
+                if let Some(id) = map.get("id") {
+                    let node = match map.get("node") {
+                        Some(node) => node.clone(),
+                        None => {
+                            let mut fields = map.clone();
+                            fields.remove("id");
+                            fields.remove("children");
+                            serde_json::Value::Object(fields)
+                        },
+                    };
+                    out.push((id.clone(), node));
+                }
+                map.values().for_each(|value| ids_and_nodes(value, out));
+            },
+            serde_json::Value::Array(values) => values.iter().for_each(|value| ids_and_nodes(value, out)),
+            _ => {},
+        }
+    }
+
+    #[test]
+    fn json_ids_match_the_document() {
+        let document = html::parse("<p class=a>x<!--c--><b>y</b></p>".to_string()).0;
+        let (stylesheet, _) = css::parse(".a { display: block }".to_string());
+        let json = |text: String| serde_json::from_str::<serde_json::Value>(&text).unwrap();
+
+        let mut in_document = Vec::new();
+        ids_and_nodes(&json(document.to_json()), &mut in_document);
+        let mut in_style = Vec::new();
+        ids_and_nodes(&json(style_tree(&document, &stylesheet).to_json()), &mut in_style);
+        // Every node but the comment.
+        assert_eq!(in_style.len(), document.preorder(document.root()).count() - 1);
+        for pair in &in_style {
+            assert!(in_document.contains(pair), "{:?}", pair);
+        }
+    }
+}
@@ -0,0 +1,938 @@
+use super::dom::{ self, Document, NodeId, NodeType, SourcePosition, SourceSpan };
+use super::html::{ self, Token };
+
+// Element categories from the HTML tree construction rules.
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
+
+const TABLE_SCOPE_BOUNDARIES: &[&str] = &["html", "table", "template"];
+
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+const HEAD_CONTENT: &[&str] = &[
+    "base", "basefont", "bgsound", "link", "meta", "noframes", "noscript", "script", "style", "title",
+];
+
+/// Elements whose text runs up to their end tag.
+const TEXT_ELEMENTS: &[&str] = &[
+    "iframe", "noembed", "noframes", "noscript", "script", "style", "textarea", "title", "xmp",
+];
+
+const FORMATTING: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+/// Elements that stop formatting from leaking into them, e.g. a `<b>` left
+/// open around a table is not reopened inside its cells.
+const FORMATTING_MARKERS: &[&str] = &["applet", "caption", "marquee", "object", "td", "template", "th"];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Start tags that close an open `p` before being inserted.
+const CLOSES_P: &[&str] = &[
+    "address", "article", "aside", "blockquote", "center", "details", "dialog", "dir", "div",
+    "dl", "fieldset", "figcaption", "figure", "footer", "form", "header", "hgroup", "listing",
+    "main", "menu", "nav", "ol", "p", "pre", "search", "section", "summary", "table", "ul",
+];
+
+/// End tags that close everything above the matching element, if it is in scope.
+const BLOCK_END_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "button", "center", "details", "dialog", "dir",
+    "div", "dl", "fieldset", "figcaption", "figure", "footer", "form", "header", "hgroup",
+    "listing", "main", "menu", "nav", "ol", "pre", "search", "section", "summary", "ul",
+];
+
+const TABLE_END_TAGS: &[&str] = &["caption", "table", "tbody", "td", "tfoot", "th", "thead", "tr"];
+
+const SPECIAL: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote",
+    "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div",
+    "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset",
+    "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img",
+    "input", "keygen", "li", "link", "listing", "main", "marquee", "menu", "meta", "nav",
+    "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script",
+    "search", "section", "select", "source", "style", "summary", "table", "tbody", "td",
+    "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    AfterBody,
+    AfterAfterBody,
+}
+
+/// An entry in the list of active formatting elements.
+enum Formatting {
+    Marker,
+    /// A formatting element and, while it is still open, its index in the
+    /// stack of open elements.
+    Element { name: String, attributes: dom::AttrMap, open_at: Option<usize> },
+}
+
+/// Builds a DOM from tokens following a simplified version of the HTML
+/// tree construction algorithm: end tags are implied, misnested tags are
+/// repaired and `html`, `head` and `body` are synthesized when missing.
+pub struct TreeBuilder {
+    mode: InsertionMode,
+    /// Mode to return to when the `Text` mode element is closed.
+    original_mode: InsertionMode,
+    /// The document being built. Its root is the `html` element from the
+    /// start, so comments before `<html>` become its first children.
+    document: Document,
+    /// Stack of open elements, each a child of the one below it.
+    open_elements: Vec<NodeId>,
+    /// Formatting elements that are reopened when closed by misnested markup.
+    active_formatting: Vec<Formatting>,
+    head: Option<NodeId>,
+    /// Set once the `Eof` token has been processed.
+    done: bool,
+    /// Source of the token being processed.
+    span: SourceSpan,
+    /// End of the previous token, where elements closed by implication end.
+    prev_end: SourcePosition,
+    /// Name of the end tag being processed, if it is one.
+    end_tag: Option<String>,
+    /// Set after `<pre>`, `<listing>` and `<textarea>`, whose first newline is dropped.
+    ignore_newline: bool,
+}
+
+impl TreeBuilder {
+    pub fn new() -> TreeBuilder {
+        TreeBuilder {
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            document: Document::new(),
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            head: None,
+            done: false,
+            span: SourceSpan { start: SourcePosition::start(), end: SourcePosition::start() },
+            prev_end: SourcePosition::start(),
+            end_tag: None,
+            ignore_newline: false,
+        }
+    }
+
+    /// Process a token read from `span` of the source.
+    pub fn process_token(&mut self, token: Token, span: SourceSpan) {
+        self.span = span;
+        self.end_tag = match token {
+            Token::EndTag { ref name } => Some(name.clone()),
+            _ => None,
+        };
+        let token = match token {
+            Token::Text(text) if self.ignore_newline => {
+                let rest = text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(&text);
+                self.span.start = self.span.start.advanced_by(&text[..text.len() - rest.len()]);
+                Token::Text(rest.to_string())
+            },
+            token => token,
+        };
+        self.ignore_newline = false;
+        self.done |= token == Token::Eof;
+        self.process(token);
+        self.prev_end = span.end;
+    }
+
+    fn process(&mut self, token: Token) {
+        // A DOCTYPE only counts before anything else; comments go wherever we are.
+        let token = match token {
+            Token::Doctype(doctype) => {
+                if self.mode == InsertionMode::Initial {
+                    self.document.doctype = Some(doctype);
+                    self.mode = InsertionMode::BeforeHtml;
+                }
+                return;
+            },
+            Token::Comment(data) => return self.insert_comment(data),
+            token => token,
+        };
+
+        match self.mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+        }
+    }
+
+    /// Return the finished document, once the `Eof` token has been processed.
+    pub fn finish(mut self) -> Document {
+        if !self.done {
+            let end = SourceSpan { start: self.prev_end, end: self.prev_end };
+            self.process_token(Token::Eof, end);
+        }
+
+        self.document
+    }
+
+    // 插入模式
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => {},
+            token => self.switch_to(InsertionMode::BeforeHtml, token),
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => {},
+            Token::Text(text) => {
+                let rest = self.skip_leading_whitespace(&text);
+                self.insert_element("html", dom::AttrMap::new());
+                self.switch_to(InsertionMode::BeforeHead, Token::Text(rest));
+            },
+            Token::StartTag { name, attributes, .. } if name == "html" => {
+                self.insert_element(&name, attributes);
+                self.mode = InsertionMode::BeforeHead;
+            },
+            Token::EndTag { ref name } if !is_one_of(name, &["head", "body", "html", "br"]) => {},
+            token => {
+                self.insert_element("html", dom::AttrMap::new());
+                self.switch_to(InsertionMode::BeforeHead, token);
+            },
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => {},
+            Token::Text(text) => {
+                let rest = self.skip_leading_whitespace(&text);
+                self.insert_element("head", dom::AttrMap::new());
+                self.switch_to(InsertionMode::InHead, Token::Text(rest));
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { name, attributes, .. } if name == "head" => {
+                self.insert_element(&name, attributes);
+                self.mode = InsertionMode::InHead;
+            },
+            Token::EndTag { ref name } if !is_one_of(name, &["head", "body", "html", "br"]) => {},
+            token => {
+                self.insert_element("head", dom::AttrMap::new());
+                self.switch_to(InsertionMode::InHead, token);
+            },
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                let (space, rest) = split_leading_whitespace(&text);
+                self.insert_leading_whitespace(space);
+                if !rest.is_empty() {
+                    self.pop();
+                    self.switch_to(InsertionMode::AfterHead, Token::Text(rest.to_string()));
+                }
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "head" => {},
+            Token::StartTag { name, attributes, self_closing } if is_one_of(&name, HEAD_CONTENT) => {
+                self.insert_element(&name, attributes);
+                if self_closing || html::is_void_element(&name) {
+                    self.pop();
+                } else if is_one_of(&name, TEXT_ELEMENTS) {
+                    self.enter_text_mode();
+                }
+            },
+            Token::EndTag { ref name } if name == "head" => {
+                self.pop();
+                self.mode = InsertionMode::AfterHead;
+            },
+            Token::EndTag { ref name } if !is_one_of(name, &["body", "html", "br"]) => {},
+            token => {
+                self.pop();
+                self.switch_to(InsertionMode::AfterHead, token);
+            },
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                let (space, rest) = split_leading_whitespace(&text);
+                self.insert_leading_whitespace(space);
+                if !rest.is_empty() {
+                    self.insert_element("body", dom::AttrMap::new());
+                    self.switch_to(InsertionMode::InBody, Token::Text(rest.to_string()));
+                }
+            },
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::StartTag { name, attributes, .. } if name == "body" => {
+                self.insert_element(&name, attributes);
+                self.mode = InsertionMode::InBody;
+            },
+            Token::StartTag { ref name, .. } if is_one_of(name, HEAD_CONTENT) => {
+                // Head content after </head> goes back into the head.
+                self.reopen_head();
+                self.switch_to(InsertionMode::InHead, token);
+            },
+            Token::StartTag { ref name, .. } if name == "head" => {},
+            Token::EndTag { ref name } if !is_one_of(name, &["body", "html", "br"]) => {},
+            token => {
+                self.insert_element("body", dom::AttrMap::new());
+                self.switch_to(InsertionMode::InBody, token);
+            },
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => {
+                self.reconstruct_formatting();
+                self.insert_text(&text);
+            },
+            // Handled in `process`.
+            Token::Comment(_) | Token::Doctype(_) => {},
+            Token::StartTag { name, attributes, self_closing } => {
+                let current = self.open_elements.last().copied();
+                let closes_itself = self_closing || html::is_void_element(&name);
+                self.in_body_start_tag(name.clone(), attributes, self_closing);
+                if is_one_of(&name, &["pre", "listing", "textarea"]) {
+                    self.ignore_newline = true;
+                }
+
+                // Void elements and `<foo/>` never get children. The tag may
+                // have closed a sibling first, as `<p/>` does an open `p`, so
+                // only a newly inserted element is popped.
+                let inserted = self.open_elements.last().copied() != current;
+                if closes_itself && inserted && self.current_tag() == name {
+                    self.pop();
+                }
+            },
+            Token::EndTag { name } => self.in_body_end_tag(name),
+            Token::Eof => {
+                while !self.open_elements.is_empty() {
+                    self.pop();
+                }
+            },
+        }
+    }
+
+    fn in_body_start_tag(&mut self, name: String, attributes: dom::AttrMap, self_closing: bool) {
+        match &*name {
+            "html" => self.merge_attributes(0, attributes),
+            "body" => {
+                if self.open_elements.len() > 1 && self.tag_name(self.open_elements[1]) == "body" {
+                    self.merge_attributes(1, attributes);
+                }
+            },
+            "head" => {},
+            _ if is_one_of(&name, HEAD_CONTENT) => {
+                self.in_head(Token::StartTag { name, attributes, self_closing });
+            },
+            _ if is_one_of(&name, HEADINGS) => {
+                self.close_p_in_button_scope();
+                if is_one_of(self.current_tag(), HEADINGS) {
+                    self.pop();
+                }
+                self.insert_element(&name, attributes);
+            },
+            _ if is_one_of(&name, CLOSES_P) || name == "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&name, attributes);
+            },
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_formatting();
+                self.insert_text_element(&name, attributes, self_closing);
+            },
+            "iframe" | "noembed" | "textarea" => {
+                self.insert_text_element(&name, attributes, self_closing);
+            },
+            "li" => {
+                self.close_list_item(&["li"]);
+                self.insert_element(&name, attributes);
+            },
+            "dd" | "dt" => {
+                self.close_list_item(&["dd", "dt"]);
+                self.insert_element(&name, attributes);
+            },
+            "button" => {
+                if self.has_in_scope(&["button"], &[]) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.insert_element(&name, attributes);
+            },
+            "option" | "optgroup" => {
+                if self.current_tag() == "option" {
+                    self.pop();
+                }
+                if name == "optgroup" && self.current_tag() == "optgroup" {
+                    self.pop();
+                }
+                self.insert_element(&name, attributes);
+            },
+            "a" => {
+                if self.find_formatting("a").is_some() {
+                    self.close_formatting("a");
+                }
+                self.reconstruct_formatting();
+                self.insert_formatting(name, attributes, self_closing);
+            },
+            _ if is_one_of(&name, FORMATTING) => {
+                self.reconstruct_formatting();
+                self.insert_formatting(name, attributes, self_closing);
+            },
+            "caption" | "colgroup" | "tbody" | "thead" | "tfoot" => {
+                self.close_in_table_scope(&["tbody", "thead", "tfoot"]);
+                self.insert_element(&name, attributes);
+            },
+            "tr" => {
+                self.close_in_table_scope(&["td", "th"]);
+                self.close_in_table_scope(&["tr"]);
+                if self.current_tag() == "table" {
+                    self.insert_element("tbody", dom::AttrMap::new());
+                }
+                self.insert_element(&name, attributes);
+            },
+            "td" | "th" => {
+                self.close_in_table_scope(&["td", "th"]);
+                if self.current_tag() == "table" {
+                    self.insert_element("tbody", dom::AttrMap::new());
+                }
+                if is_one_of(self.current_tag(), &["tbody", "thead", "tfoot"]) {
+                    self.insert_element("tr", dom::AttrMap::new());
+                }
+                self.insert_element(&name, attributes);
+            },
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&name, attributes);
+            },
+        }
+    }
+
+    fn in_body_end_tag(&mut self, name: String) {
+        match &*name {
+            "body" => {
+                if self.has_in_scope(&["body"], &[]) {
+                    self.mode = InsertionMode::AfterBody;
+                }
+            },
+            "html" => {
+                if self.has_in_scope(&["body"], &[]) {
+                    self.switch_to(InsertionMode::AfterBody, Token::EndTag { name });
+                }
+            },
+            "p" => {
+                if !self.has_in_scope(&["p"], &["button"]) {
+                    self.insert_element("p", dom::AttrMap::new());
+                }
+                self.close_p();
+            },
+            "li" => {
+                if self.has_in_scope(&["li"], &["ol", "ul"]) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.pop_until(&["li"]);
+                }
+            },
+            // `</br>` is treated as `<br>`, as in browsers.
+            "br" => self.in_body(Token::StartTag { name, attributes: dom::AttrMap::new(), self_closing: false }),
+            "dd" | "dt" => {
+                if self.has_in_scope(&[&*name], &[]) {
+                    self.generate_implied_end_tags(Some(&name));
+                    self.pop_until(&[&*name]);
+                }
+            },
+            _ if is_one_of(&name, HEADINGS) => {
+                if self.has_in_scope(HEADINGS, &[]) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(HEADINGS);
+                }
+            },
+            _ if is_one_of(&name, BLOCK_END_TAGS) => {
+                if self.has_in_scope(&[&*name], &[]) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[&*name]);
+                }
+            },
+            _ if is_one_of(&name, TABLE_END_TAGS) => self.close_in_table_scope(&[&*name]),
+            _ if is_one_of(&name, FORMATTING) => self.close_formatting(&name),
+            _ => self.any_other_end_tag(&name),
+        }
+    }
+
+    fn any_other_end_tag(&mut self, name: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let open = self.tag_name(self.open_elements[index]);
+            if open == name {
+                self.generate_implied_end_tags(Some(name));
+                while self.open_elements.len() > index {
+                    self.pop();
+                }
+                return;
+            }
+            if is_one_of(open, SPECIAL) {
+                return;
+            }
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => self.insert_text(&text),
+            Token::EndTag { .. } => {
+                self.pop();
+                self.mode = self.original_mode;
+            },
+            token => {
+                self.pop();
+                let mode = self.original_mode;
+                self.switch_to(mode, token);
+            },
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::EndTag { ref name } if name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            Token::Eof => self.in_body(token),
+            token => self.switch_to(InsertionMode::InBody, token),
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            Token::StartTag { ref name, .. } if name == "html" => self.in_body(token),
+            Token::Eof => self.in_body(token),
+            token => self.switch_to(InsertionMode::InBody, token),
+        }
+    }
+
+    // 通用函数
+    fn switch_to(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process(token);
+    }
+
+    fn tag_name(&self, id: NodeId) -> &str {
+        match self.document[id].node_type {
+            NodeType::Element(ref elem) => &elem.tag_name,
+            _ => "",
+        }
+    }
+
+    fn current_tag(&self) -> &str {
+        self.open_elements.last().map_or("", |&id| self.tag_name(id))
+    }
+
+    /// Where new nodes go: the current element, or the root before it is opened.
+    fn insertion_parent(&self) -> NodeId {
+        self.open_elements.last().copied().unwrap_or_else(|| self.document.root())
+    }
+
+    fn insert_element(&mut self, name: &str, attributes: dom::AttrMap) {
+        if is_one_of(name, FORMATTING_MARKERS) {
+            self.active_formatting.push(Formatting::Marker);
+        }
+        let element = if self.open_elements.is_empty() {
+            // The root already exists; opening it just fills it in.
+            let root = self.document.root();
+            let node = self.document.node_mut(root);
+            node.node_type = NodeType::Element(dom::ElementData { tag_name: name.to_string(), attributes });
+            node.source = Some(SourceSpan { start: self.span.start, end: self.span.start });
+            root
+        } else {
+            self.create_element(name.to_string(), attributes)
+        };
+        if name == "head" {
+            self.head = Some(element);
+        }
+        self.open_elements.push(element);
+    }
+
+    /// A new element starting at the current token, appended to the current
+    /// element; its end is set when it is popped.
+    fn create_element(&mut self, name: String, attributes: dom::AttrMap) -> NodeId {
+        let element = self.document.create_element(name, attributes);
+        self.document.node_mut(element).source = Some(SourceSpan { start: self.span.start, end: self.span.start });
+        let parent = self.insertion_parent();
+        self.document.append(parent, element);
+
+        element
+    }
+
+    /// Insert an element whose content the tokenizer reads as raw text.
+    fn insert_text_element(&mut self, name: &str, attributes: dom::AttrMap, self_closing: bool) {
+        self.insert_element(name, attributes);
+        // The tokenizer reads no text after `<textarea/>`, and the element
+        // is popped straight away, so there is nothing to wait for.
+        if !self_closing {
+            self.enter_text_mode();
+        }
+    }
+
+    fn enter_text_mode(&mut self) {
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn insert_comment(&mut self, data: String) {
+        let comment = self.document.create_comment(data);
+        self.document.node_mut(comment).source = Some(self.span);
+        // Comments after `</body>` follow the body in the html element.
+        let parent = match self.mode {
+            InsertionMode::AfterBody | InsertionMode::AfterAfterBody => self.document.root(),
+            _ => self.insertion_parent(),
+        };
+        self.document.append(parent, comment);
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        let span = self.span;
+        self.insert_text_from(text, span);
+    }
+
+    /// Insert whitespace split off the front of the current text token, which
+    /// then starts after it.
+    fn insert_leading_whitespace(&mut self, space: &str) {
+        let end = self.span.start.advanced_by(space);
+        self.insert_text_from(space, SourceSpan { start: self.span.start, end });
+        self.span.start = end;
+    }
+
+    /// Drop whitespace from the front of the current text token, as the
+    /// modes before `<head>` do.
+    fn skip_leading_whitespace(&mut self, text: &str) -> String {
+        let (space, rest) = split_leading_whitespace(text);
+        self.span.start = self.span.start.advanced_by(space);
+
+        rest.to_string()
+    }
+
+    fn insert_text_from(&mut self, text: &str, span: SourceSpan) {
+        if text.is_empty() {
+            return;
+        }
+
+        let parent = *self.open_elements.last().expect("text is only inserted inside html");
+        if let Some(last) = self.document.last_child(parent) {
+            let node = self.document.node_mut(last);
+            if let NodeType::Text(ref mut data) = node.node_type {
+                data.push_str(text);
+                if let Some(ref mut source) = node.source {
+                    source.end = span.end;
+                }
+                return;
+            }
+        }
+
+        let node = self.document.create_text(text.to_string());
+        self.document.node_mut(node).source = Some(span);
+        self.document.append(parent, node);
+    }
+
+    fn merge_attributes(&mut self, index: usize, attributes: dom::AttrMap) {
+        let element = self.open_elements[index];
+        if let NodeType::Element(ref mut elem) = self.document.node_mut(element).node_type {
+            for (name, value) in attributes {
+                if !elem.attributes.contains_key(&name) {
+                    elem.attributes.insert(name, value);
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) {
+        let node = self.open_elements.pop().expect("stack of open elements is empty");
+        // An element ends with its own end tag, or else with its last content.
+        let end = if self.end_tag.as_deref() == Some(self.tag_name(node)) { self.span.end } else { self.prev_end };
+        if let Some(ref mut source) = self.document.node_mut(node).source {
+            source.end = end;
+        }
+        if is_one_of(self.tag_name(node), FORMATTING_MARKERS) {
+            self.clear_formatting_to_marker();
+        }
+        let index = self.open_elements.len();
+        for entry in &mut self.active_formatting {
+            if let Formatting::Element { ref mut open_at, .. } = *entry {
+                if *open_at == Some(index) {
+                    *open_at = None;
+                }
+            }
+        }
+    }
+
+    /// Pop elements until one of the named elements has been popped.
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(&node) = self.open_elements.last() {
+            let done = is_one_of(self.tag_name(node), names);
+            self.pop();
+            if done {
+                break;
+            }
+        }
+    }
+
+    fn reopen_head(&mut self) {
+        if let Some(head) = self.head {
+            self.open_elements.push(head);
+        }
+    }
+
+    fn has_in_scope(&self, names: &[&str], extra_boundaries: &[&str]) -> bool {
+        self.has_in_specific_scope(names, SCOPE_BOUNDARIES, extra_boundaries)
+    }
+
+    fn has_in_specific_scope(&self, names: &[&str], boundaries: &[&str], extra_boundaries: &[&str]) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            let name = self.tag_name(node);
+            if is_one_of(name, names) {
+                return true;
+            }
+            if is_one_of(name, boundaries) || is_one_of(name, extra_boundaries) {
+                return false;
+            }
+        }
+
+        return false;
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while is_one_of(self.current_tag(), IMPLIED_END_TAGS) && Some(self.current_tag()) != except {
+            self.pop();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.has_in_scope(&["p"], &["button"]) {
+            self.close_p();
+        }
+    }
+
+    fn close_in_table_scope(&mut self, names: &[&str]) {
+        if self.has_in_specific_scope(names, TABLE_SCOPE_BOUNDARIES, &[]) {
+            self.generate_implied_end_tags(None);
+            self.pop_until(names);
+        }
+    }
+
+    /// Close an open `li` (or `dd`/`dt`) before starting a new one.
+    fn close_list_item(&mut self, names: &[&str]) {
+        for index in (0..self.open_elements.len()).rev() {
+            let open = self.tag_name(self.open_elements[index]);
+            if is_one_of(open, names) {
+                let open = open.to_string();
+                self.generate_implied_end_tags(Some(&open));
+                self.pop_until(&[&*open]);
+                break;
+            }
+            if is_one_of(open, SPECIAL) && !is_one_of(open, &["address", "div", "p"]) {
+                break;
+            }
+        }
+        self.close_p_in_button_scope();
+    }
+
+    // 格式化元素
+    fn insert_formatting(&mut self, name: String, attributes: dom::AttrMap, self_closing: bool) {
+        // `<b/>` is closed straight away, so there is nothing to reopen later.
+        if self_closing {
+            return self.insert_element(&name, attributes);
+        }
+        self.active_formatting.push(Formatting::Element {
+            name: name.clone(),
+            attributes: attributes.clone(),
+            open_at: Some(self.open_elements.len()),
+        });
+        self.insert_element(&name, attributes);
+    }
+
+    /// Index of the last active formatting element with this name, after the last marker.
+    fn find_formatting(&self, name: &str) -> Option<usize> {
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match *entry {
+                Formatting::Marker => return None,
+                Formatting::Element { name: ref entry_name, .. } if entry_name == name => return Some(index),
+                Formatting::Element { .. } => {},
+            }
+        }
+
+        return None;
+    }
+
+    /// A simplified adoption agency: close the formatting element and
+    /// everything opened inside it, leaving the inner formatting elements to
+    /// be reopened by the next piece of content.
+    fn close_formatting(&mut self, name: &str) {
+        let index = match self.find_formatting(name) {
+            Some(index) => index,
+            None => return self.any_other_end_tag(name),
+        };
+
+        let open_at = match self.active_formatting[index] {
+            Formatting::Element { open_at, .. } => open_at,
+            Formatting::Marker => None,
+        };
+        match open_at {
+            Some(depth) if self.has_in_scope(&[name], &[]) => {
+                self.generate_implied_end_tags(None);
+                while self.open_elements.len() > depth {
+                    self.pop();
+                }
+                self.active_formatting.remove(index);
+            },
+            Some(_) => {},
+            None => { self.active_formatting.remove(index); },
+        }
+    }
+
+    /// Reopen formatting elements that were closed by misnested end tags.
+    fn reconstruct_formatting(&mut self) {
+        let start = self.active_formatting.iter()
+            .rposition(|entry| match *entry {
+                Formatting::Marker => true,
+                Formatting::Element { open_at, .. } => open_at.is_some(),
+            })
+            .map_or(0, |index| index + 1);
+
+        for index in start..self.active_formatting.len() {
+            let element = match self.active_formatting[index] {
+                Formatting::Element { ref name, ref attributes, .. } => (name.clone(), attributes.clone()),
+                Formatting::Marker => continue,
+            };
+            let element = self.create_element(element.0, element.1);
+            if let Formatting::Element { ref mut open_at, .. } = self.active_formatting[index] {
+                *open_at = Some(self.open_elements.len());
+            }
+            self.open_elements.push(element);
+        }
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if let Formatting::Marker = entry {
+                break;
+            }
+        }
+    }
+}
+
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.contains(&name)
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.chars().all(|c| c.is_ascii_whitespace())
+}
+
+fn split_leading_whitespace(text: &str) -> (&str, &str) {
+    let end = text.find(|c: char| !c.is_ascii_whitespace()).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html;
+
+    /// The body of the document parsed from `source`, dumped without its
+    /// `<body>` line and indentation.
+    fn body(source: &str) -> String {
+        let dump = html::parse(source.to_string()).0.dump();
+        let start = dump.find("  <body>\n").expect("no body") + "  <body>\n".len();
+        dump[start..].lines().map(|line| format!("{}\n", &line[4..])).collect()
+    }
+
+    #[test]
+    fn missing_html_head_and_body_are_implied() {
+        let dump = html::parse("<title>t</title><p>x".to_string()).0.dump();
+        assert_eq!(dump, "<html>\n  <head>\n    <title>\n      \"t\"\n  <body>\n    <p>\n      \"x\"\n");
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        assert_eq!(body("<p>a<div>b</div>"), "<p>\n  \"a\"\n<div>\n  \"b\"\n");
+        assert_eq!(body("<p>a<p>b"), "<p>\n  \"a\"\n<p>\n  \"b\"\n");
+        assert_eq!(body("<ul><li>a<li>b</ul>"), "<ul>\n  <li>\n    \"a\"\n  <li>\n    \"b\"\n");
+        assert_eq!(
+            body("<dl><dt>a<dd>b<dt>c</dl>"),
+            "<dl>\n  <dt>\n    \"a\"\n  <dd>\n    \"b\"\n  <dt>\n    \"c\"\n",
+        );
+        assert_eq!(body("<div><p>a</div>b"), "<div>\n  <p>\n    \"a\"\n\"b\"\n");
+    }
+
+    #[test]
+    fn content_after_body_goes_back_into_it() {
+        assert_eq!(body("<html><body><p>a</body></html><p>b"), "<p>\n  \"a\"\n<p>\n  \"b\"\n");
+        let dump = html::parse("<head></head>\n<body></body>\n<!--c-->".to_string()).0.dump();
+        assert_eq!(dump, "<html>\n  <head>\n  \"\\n\"\n  <body>\n    \"\\n\"\n  <!--c-->\n");
+    }
+
+    #[test]
+    fn misnested_formatting_is_reopened() {
+        assert_eq!(body("<b><i>a</b>b</i>c"), "<b>\n  <i>\n    \"a\"\n<i>\n  \"b\"\n\"c\"\n");
+        assert_eq!(body("<p><b>a</p>b"), "<p>\n  <b>\n    \"a\"\n<b>\n  \"b\"\n");
+        assert_eq!(body("<a>x<a>y"), "<a>\n  \"x\"\n<a>\n  \"y\"\n");
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            body("<table><tr><td>a<td>b<tr><td>c</table>"),
+            "<table>\n  <tbody>\n    <tr>\n      <td>\n        \"a\"\n      <td>\n        \"b\"\n    <tr>\n      <td>\n        \"c\"\n",
+        );
+        assert_eq!(body("<table><td>a</table>"), "<table>\n  <tbody>\n    <tr>\n      <td>\n        \"a\"\n");
+        assert_eq!(
+            body("<!DOCTYPE html><p>a<table><tr><td>b</table>"),
+            "<p>\n  \"a\"\n<table>\n  <tbody>\n    <tr>\n      <td>\n        \"b\"\n",
+        );
+        assert_eq!(
+            body("<table><caption>c</caption><thead><tr><th>h</table>"),
+            "<table>\n  <caption>\n    \"c\"\n  <thead>\n    <tr>\n      <th>\n        \"h\"\n",
+        );
+    }
+
+    #[test]
+    fn self_closing_tags_have_no_children() {
+        assert_eq!(body("<div/>x<br>y<b/>z"), "<div>\n\"x\"\n<br>\n\"y\"\n<b>\n\"z\"\n");
+    }
+
+    #[test]
+    fn self_closing_text_elements_leave_text_mode() {
+        for name in ["textarea", "xmp", "iframe", "noembed"] {
+            let source = format!("<div><{}/><b>x</b></div><p>y", name);
+            let expected = format!("<div>\n  <{}>\n  <b>\n    \"x\"\n<p>\n  \"y\"\n", name);
+            assert_eq!(body(&source), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn self_closing_tag_after_implied_end_tag() {
+        assert_eq!(body("<p>a<p/>b"), "<p>\n  \"a\"\n<p>\n\"b\"\n");
+        assert_eq!(body("<ul><li>a<li/>b"), "<ul>\n  <li>\n    \"a\"\n  <li>\n  \"b\"\n");
+        assert_eq!(body("<h1>a<h2/>b"), "<h1>\n  \"a\"\n<h2>\n\"b\"\n");
+        assert_eq!(
+            body("<select><option>a<option/>b</select>"),
+            "<select>\n  <option>\n    \"a\"\n  <option>\n  \"b\"\n",
+        );
+        assert_eq!(
+            body("<table><tr><td>a<td/>b</table>"),
+            "<table>\n  <tbody>\n    <tr>\n      <td>\n        \"a\"\n      <td>\n      \"b\"\n",
+        );
+    }
+}